@@ -0,0 +1,83 @@
+//! Compares allocations on the recorder packet path with and without the buffer pool.
+//!
+//! Run with `cargo bench --bench recorder_buffers`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::VecDeque,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/recorder/buffer_pool.rs"]
+mod buffer_pool;
+
+use buffer_pool::BufferPool;
+
+const STREAMS: usize = 500;
+/// 5 seconds of audio & video at 50 packets per second each.
+const ROUNDS: usize = 500;
+const PACKET_SIZE: usize = 1200;
+/// How many rounds the recorder lags behind.
+const QUEUE_DEPTH: usize = 4;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Feeds packets of all the streams through a queue drained by the recorder with a lag.
+fn run<B, F: FnMut(&[i8]) -> B>(name: &str, mut copy: F) {
+    let packet = vec![0i8; PACKET_SIZE];
+    let mut queue = VecDeque::with_capacity(STREAMS * 2 * (QUEUE_DEPTH + 1));
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let started_at = Instant::now();
+
+    for _ in 0..ROUNDS {
+        for _ in 0..STREAMS * 2 {
+            queue.push_back(copy(&packet));
+        }
+
+        while queue.len() > STREAMS * 2 * QUEUE_DEPTH {
+            drop(queue.pop_front());
+        }
+    }
+
+    queue.clear();
+
+    let elapsed = started_at.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    let packets = ROUNDS * STREAMS * 2;
+
+    println!(
+        "{:<8} {:>10} packets {:>10} allocations {:>8.3} allocations/packet {:>10.1?}",
+        name,
+        packets,
+        allocations,
+        allocations as f64 / packets as f64,
+        elapsed,
+    );
+}
+
+fn main() {
+    println!("{} streams, {} bytes packets", STREAMS, PACKET_SIZE);
+    run("vec", |packet| packet.to_vec());
+
+    let pool = BufferPool::new(STREAMS * 2 * (QUEUE_DEPTH + 1));
+    run("pool", |packet| pool.copy_from(packet));
+}
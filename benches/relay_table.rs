@@ -0,0 +1,177 @@
+//! Compares the relay path latency of the locked switchboard and the relay table snapshot
+//! under a storm of readers joining streams like at the start of lessons.
+//!
+//! Relay threads look up the readers of a publisher for each packet while a signalling
+//! thread keeps adding readers. The snapshot is the sharded map the relay table is built on
+//! published through `ArcSwap` the same way `LockedSwitchboard` does.
+//!
+//! Run with `cargo bench --bench relay_table`.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use fnv::FnvHashMap;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/sharded_map.rs"]
+mod sharded_map;
+
+use sharded_map::ShardedMap;
+
+const STREAMS: u64 = 100;
+const READERS_PER_STREAM: u64 = 10;
+const JOINS: u64 = 20_000;
+const RELAY_THREADS: usize = 4;
+/// Same as `RELAY_TABLE_SHARDS` in the switchboard.
+const SHARDS: usize = 256;
+/// Time spent by a signalling request holding the switchboard write lock
+/// on logging, Janus callbacks and so on.
+const SIGNALLING_WORK: Duration = Duration::from_micros(50);
+
+type Readers = Vec<u64>;
+
+trait Relay: Send + Sync + 'static {
+    fn relay(&self, publisher: u64) -> u64;
+    fn join(&self, publisher: u64, reader: u64);
+}
+
+/// Relaying under the switchboard read lock.
+struct Locked(RwLock<FnvHashMap<u64, Readers>>);
+
+impl Relay for Locked {
+    fn relay(&self, publisher: u64) -> u64 {
+        let table = self.0.read().expect("Failed to acquire read lock");
+        table
+            .get(&publisher)
+            .map(|readers| readers.iter().sum())
+            .unwrap_or(0)
+    }
+
+    fn join(&self, publisher: u64, reader: u64) {
+        let mut table = self.0.write().expect("Failed to acquire write lock");
+        table.entry(publisher).or_default().push(reader);
+        spin(SIGNALLING_WORK);
+    }
+}
+
+/// Relaying with the latest snapshot while writers publish new versions
+/// rebuilding the changed entries only.
+struct Snapshot {
+    table: Mutex<FnvHashMap<u64, Readers>>,
+    snapshot: ArcSwap<ShardedMap<u64, Readers>>,
+}
+
+impl Relay for Snapshot {
+    fn relay(&self, publisher: u64) -> u64 {
+        let snapshot = self.snapshot.load();
+        snapshot
+            .get(&publisher)
+            .map(|readers| readers.iter().sum())
+            .unwrap_or(0)
+    }
+
+    fn join(&self, publisher: u64, reader: u64) {
+        let mut table = self.table.lock().expect("Failed to acquire lock");
+        let readers = table.entry(publisher).or_default();
+        readers.push(reader);
+        spin(SIGNALLING_WORK);
+
+        let change = (publisher, Some(readers.clone()));
+        let next = self.snapshot.load().with_changes(vec![change]);
+        self.snapshot.store(Arc::new(next));
+    }
+}
+
+fn spin(duration: Duration) {
+    let started_at = Instant::now();
+    while started_at.elapsed() < duration {}
+}
+
+fn initial_table() -> FnvHashMap<u64, Readers> {
+    (0..STREAMS)
+        .map(|publisher| {
+            let readers = (0..READERS_PER_STREAM)
+                .map(|reader| publisher * 1000 + reader)
+                .collect();
+
+            (publisher, readers)
+        })
+        .collect()
+}
+
+fn run<R: Relay>(name: &str, relay: R) {
+    let relay = Arc::new(relay);
+    let is_done = Arc::new(AtomicBool::new(false));
+
+    let relay_threads = (0..RELAY_THREADS)
+        .map(|n| {
+            let relay = relay.clone();
+            let is_done = is_done.clone();
+
+            thread::spawn(move || {
+                let mut latencies = Vec::new();
+                let mut packet = n as u64;
+
+                while !is_done.load(Ordering::Relaxed) {
+                    let started_at = Instant::now();
+                    let checksum = relay.relay(packet % STREAMS);
+                    latencies.push(started_at.elapsed());
+                    packet = packet.wrapping_add(checksum % 7 + 1);
+                }
+
+                latencies
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let started_at = Instant::now();
+
+    for n in 0..JOINS {
+        relay.join(n % STREAMS, 1_000_000 + n);
+    }
+
+    let storm_duration = started_at.elapsed();
+    is_done.store(true, Ordering::Relaxed);
+
+    let mut latencies = relay_threads
+        .into_iter()
+        .flat_map(|relay_thread| relay_thread.join().expect("Relay thread panicked"))
+        .collect::<Vec<_>>();
+
+    latencies.sort_unstable();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+
+    println!(
+        "{:<10} joins: {:>6} in {:>10?}, p50: {:>10?}, p99: {:>10?}, p99.9: {:>10?}, max: {:>10?}",
+        name,
+        JOINS,
+        storm_duration,
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        latencies[latencies.len() - 1],
+    );
+}
+
+fn main() {
+    run("rwlock", Locked(RwLock::new(initial_table())));
+
+    let table = initial_table();
+    let changes = table.iter().map(|(k, v)| (*k, Some(v.clone())));
+    let snapshot = ShardedMap::new(SHARDS).with_changes(changes);
+
+    run(
+        "snapshot",
+        Snapshot {
+            snapshot: ArcSwap::from_pointee(snapshot),
+            table: Mutex::new(table),
+        },
+    );
+}
@@ -11,6 +11,8 @@ SCRIPT_ABS_DIR=$(dirname ${SCRIPT_ABS_PATH})
 RTC_ID=$1
 BACKEND=$2
 BUCKET=$3
+# Optional. When specified only this record segment gets uploaded.
+FILE=$4
 
 if [[ ! ${RTC_ID} ]]; then $(REPORT_ERROR "RTC_ID isn't specified"); exit 1; fi
 if [[ ! ${BACKEND} ]]; then $(REPORT_ERROR "BACKEND isn't specified"); exit 1; fi
@@ -44,6 +46,17 @@ AWS=${AWS:-"aws --endpoint-url=${AWS_ENDPOINT} --region=${AWS_REGION}"}
 # Working directory.
 cd ${RECORDINGS_DIR}/${RTC_ID}
 
+# Upload a single segment closed while the stream is still being recorded.
+# Remember its URI so the final upload doesn't ship it once again.
+if [[ ${FILE} ]]; then
+  DUMP_FILE="s3://${BUCKET}/${RTC_ID}_dump/${FILE}"
+  ${AWS} s3 cp ${FILE} ${DUMP_FILE} \
+    --only-show-errors \
+    --cache-control 'no-cache'
+  echo "${FILE} ${DUMP_FILE}" >> uploaded.txt
+  exit 0
+fi
+
 # Try to acquire lock
 if { set -C; 2>/dev/null > vacuum_${RTC_ID}.lock; }; then
   trap "rm -f vacuum_${RTC_ID}.lock" EXIT
@@ -53,12 +66,22 @@ else
 fi
 
 # Remove artifacts from possible previous run to avoid concat duplication.
-rm -f dumps.txt
+rm -f dumps.txt upload_done
+
+# Encrypted segments are decrypted by the plugin before the upload except for the ones uploaded live.
+for FILE in $(ls *.mjr *.mjr.enc 2>/dev/null | sed 's/\.enc$//' | sort -u); do
+  UPLOADED_FILE=$(grep "^${FILE} " uploaded.txt 2>/dev/null | cut -d ' ' -f 2)
+  if [[ ${UPLOADED_FILE} ]]; then
+    echo ${UPLOADED_FILE} >> dumps.txt
+    continue
+  fi
 
-for FILE in *.mjr; do
   DUMP_FILE="s3://${BUCKET}/${RTC_ID}_dump/${FILE}"
   ${AWS} s3 cp ${FILE} ${DUMP_FILE} \
     --only-show-errors \
     --cache-control 'no-cache'
   echo ${DUMP_FILE} >> dumps.txt
 done
+
+# Mark the record as uploaded only once all the segments are shipped.
+touch upload_done
@@ -1,12 +1,22 @@
-use std::{net::SocketAddr, thread};
+use std::{net::SocketAddr, thread, time::Duration};
 
 use anyhow::Result;
 use once_cell::sync::OnceCell;
 use prometheus::{Encoder, Registry, TextEncoder};
 
-use crate::{conf::Config, recorder::recorder, register};
+use crate::{
+    conf::Config,
+    fan_out::{fan_out, FanOut},
+    recorder::{clean_records, free_space, recorder},
+    register,
+};
 use crate::{message_handler::JanusSender, recorder::RecorderHandlesCreator};
-use crate::{metrics::Metrics, switchboard::LockedSwitchboard as Switchboard};
+use crate::{
+    metrics::Metrics,
+    switchboard::{LockedSwitchboard as Switchboard, SwitchboardError},
+};
+
+const STREAMS_VACUUM_INTERVAL: Duration = Duration::from_secs(1);
 
 pub static APP: OnceCell<App> = OnceCell::new();
 
@@ -25,6 +35,7 @@ pub struct App {
     pub recorders_creator: RecorderHandlesCreator,
     pub janus_sender: JanusSender,
     pub metrics: Metrics,
+    pub fan_out: FanOut,
     pub fir_interval: chrono::Duration,
 }
 
@@ -45,7 +56,7 @@ impl App {
                 async_std::task::spawn(healh_check);
             });
         }
-        let (recorder, handles_creator) =
+        let (recorders, handles_creator) =
             recorder(config.recordings.clone(), config.metrics.clone());
         let metrics_registry = Registry::new();
         let metrics = Metrics::new(&metrics_registry)?;
@@ -54,31 +65,60 @@ impl App {
             config.metrics.bind_addr,
         ));
 
-        let app = App::new(config, handles_creator, metrics)?;
+        let (fan_out_workers, fan_out) = fan_out(&config.fan_out);
+        let app = App::new(config, handles_creator, metrics, fan_out)?;
         APP.set(app).expect("Already initialized");
-        thread::spawn(|| recorder.start());
+
+        for recorder in recorders {
+            thread::spawn(|| recorder.start());
+        }
+
+        // Recover records interrupted by a crash. Streams can't be recorded again until
+        // their records are recovered.
+        thread::spawn(|| {
+            if let Ok(app) = app!() {
+                let encryption = app.config.recordings.encryption.as_ref();
+                app.recorders_creator.recovery().recover(encryption);
+            }
+        });
+
+        for worker in fan_out_workers {
+            thread::spawn(|| worker.start());
+        }
 
         thread::spawn(|| loop {
             if let Ok(app) = app!() {
                 let _ = app.switchboard.with_read_lock(|switchboard| {
                     Metrics::observe_switchboard(&switchboard);
-                    Ok(())
+                    Ok::<_, SwitchboardError>(())
                 });
                 thread::sleep(app.config.metrics.switchboard_metrics_load_interval)
             }
         });
 
+        if app!()?.config.recordings.enabled {
+            thread::spawn(|| loop {
+                if let Ok(app) = app!() {
+                    app.clean_records();
+                    thread::sleep(app.config.recordings.janitor_interval)
+                }
+            });
+        }
+
         thread::spawn(|| {
             if let Ok(app) = app!() {
-                if let Err(err) = app.switchboard.vacuum_publishers_loop(
-                    app.config.general.vacuum_interval,
-                    app.config.general.sessions_ttl,
-                ) {
-                    err!("Vacuum publishers loop failed: {}", err);
+                if let Err(err) = app.switchboard.vacuum_sessions_loop(&app.config.general) {
+                    err!("Vacuum sessions loop failed: {}", err);
                 }
             }
         });
 
+        thread::spawn(|| {
+            if let Ok(app) = app!() {
+                app.switchboard.vacuum_streams_loop(STREAMS_VACUUM_INTERVAL);
+            }
+        });
+
         Ok(())
     }
 
@@ -86,8 +126,12 @@ impl App {
         config: Config,
         recorders_creator: RecorderHandlesCreator,
         metrics: Metrics,
+        fan_out: FanOut,
     ) -> Result<Self> {
-        let switchboard_cfg = config.switchboard.clone();
+        let switchboard_cfg = config
+            .switchboard
+            .clone()
+            .set_fan_out_workers(config.fan_out.workers);
 
         let capacity = config
             .registry
@@ -106,8 +150,45 @@ impl App {
             recorders_creator,
             janus_sender: JanusSender::new(),
             metrics,
+            fan_out,
         })
     }
+
+    /// Applies the recordings retention policy and updates disk usage metrics.
+    fn clean_records(&self) {
+        let config = &self.config.recordings;
+
+        let uploading_streams = self.recorders_creator.uploader().uploading_streams();
+
+        let is_recording = |stream_id| {
+            self.switchboard
+                .with_read_lock(|switchboard| {
+                    Ok::<_, SwitchboardError>(switchboard.stream_recorder(stream_id).is_some())
+                })
+                .unwrap_or(false)
+        };
+
+        let retained_bytes = clean_records(config, is_recording, |stream_id| {
+            uploading_streams.contains(&stream_id)
+        });
+
+        let retained_bytes = match retained_bytes {
+            Ok(retained_bytes) => retained_bytes,
+            Err(err) => {
+                err!("Failed to clean records: {:?}", err);
+                return;
+            }
+        };
+
+        for (volume, retained_bytes) in retained_bytes {
+            match free_space(&volume) {
+                Ok(free_space) => {
+                    Metrics::observe_recordings_disk(&volume, free_space, retained_bytes)
+                }
+                Err(err) => err!("Failed to get free space of {}: {:?}", volume, err),
+            }
+        }
+    }
 }
 
 async fn start_health_check(bind_addr: SocketAddr) {
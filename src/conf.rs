@@ -2,7 +2,12 @@ use std::{net::SocketAddr, path::Path, time::Duration};
 
 use anyhow::Result;
 
-use crate::{janus_rtp::AudioLevel, recorder};
+use crate::{
+    fan_out,
+    janus_rtp::AudioLevel,
+    recorder,
+    switchboard::{SessionsPolicy, TakeoverPolicy},
+};
 
 const CONFIG_FILE_NAME: &str = "janus.plugin.conference.toml";
 
@@ -17,6 +22,8 @@ pub struct Config {
     pub metrics: Metrics,
     pub registry: Option<RegistryConfig>,
     pub switchboard: SwitchboardConfig,
+    #[serde(default)]
+    pub fan_out: fan_out::Config,
 }
 
 impl Config {
@@ -45,6 +52,24 @@ pub struct SwitchboardConfig {
     #[serde(default = "SwitchboardConfig::default_max_sessions_per_agent")]
     pub max_sessions_per_agent: usize,
     pub max_agents: Option<usize>,
+    /// What to do with extra sessions of an agent.
+    #[serde(default)]
+    pub sessions_policy: SessionsPolicy,
+    /// How long readers stay attached to a stream after its publisher is lost.
+    #[serde(default, with = "humantime_serde")]
+    pub publisher_grace_period: Option<Duration>,
+    /// How long readers are relayed the previous publisher waiting for a keyframe of the new one.
+    #[serde(
+        default = "SwitchboardConfig::default_takeover_keyframe_timeout",
+        with = "humantime_serde"
+    )]
+    pub takeover_keyframe_timeout: Duration,
+    /// Who may replace the publisher of an existing stream unless specified in `stream.create`.
+    #[serde(default)]
+    pub takeover_policy: TakeoverPolicy,
+    /// Number of fan-out workers to split stream readers among. Taken from `fan_out` config.
+    #[serde(skip)]
+    pub fan_out_workers: usize,
 }
 
 impl SwitchboardConfig {
@@ -52,6 +77,10 @@ impl SwitchboardConfig {
         1
     }
 
+    fn default_takeover_keyframe_timeout() -> Duration {
+        Duration::from_secs(2)
+    }
+
     pub fn set_max_agents_if_empty(mut self, max_agents: usize) -> Self {
         if self.max_agents.is_none() {
             self.max_agents = Some(max_agents);
@@ -59,6 +88,11 @@ impl SwitchboardConfig {
 
         self
     }
+
+    pub fn set_fan_out_workers(mut self, workers: usize) -> Self {
+        self.fan_out_workers = workers;
+        self
+    }
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -85,6 +119,12 @@ pub struct General {
     pub fir_interval: Duration,
     #[serde(with = "humantime_serde")]
     pub sessions_ttl: Duration,
+    /// How long a publisher may not send RTP packets. Defaults to `vacuum_interval`.
+    #[serde(default, with = "humantime_serde")]
+    pub publisher_timeout: Option<Duration>,
+    /// How long a reader may not send RTCP packets. Readers aren't checked if not set.
+    #[serde(default, with = "humantime_serde")]
+    pub reader_timeout: Option<Duration>,
     pub health_check_addr: SocketAddr,
 }
 
@@ -0,0 +1,188 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    time::Instant,
+};
+
+use anyhow::Result;
+use janus::PluginRtpPacket;
+use janus_plugin_sys::plugin::janus_plugin_rtp_extensions;
+
+use crate::{
+    janus_callbacks,
+    janus_rtp::JanusRtpHeader,
+    metrics::Metrics,
+    switchboard::{ForwardingTable, RelayReader},
+};
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct Config {
+    /// Number of fan-out threads. Zero disables parallel fan-out.
+    #[serde(default)]
+    pub workers: usize,
+    /// Minimum number of readers of a stream to relay its packets in parallel.
+    #[serde(default = "Config::default_threshold")]
+    pub threshold: usize,
+    /// Maximum number of packets waiting for each worker.
+    #[serde(default = "Config::default_queue_capacity")]
+    pub queue_capacity: usize,
+}
+
+impl Config {
+    fn default_threshold() -> usize {
+        500
+    }
+
+    fn default_queue_capacity() -> usize {
+        1000
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            workers: 0,
+            threshold: Self::default_threshold(),
+            queue_capacity: Self::default_queue_capacity(),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// A copy of an RTP packet to be relayed to a share of the stream's readers.
+struct Job {
+    forwarding: Arc<ForwardingTable>,
+    buffer: Vec<i8>,
+    is_video: bool,
+    extensions: janus_plugin_rtp_extensions,
+    dispatched_at: Instant,
+}
+
+/// Relays packets of streams with many readers on a pool of threads.
+#[derive(Debug)]
+pub struct FanOut {
+    workers: Vec<SyncSender<Job>>,
+    threshold: usize,
+}
+
+impl FanOut {
+    /// Whether packets of the stream should be relayed by workers rather than inline.
+    pub fn is_parallel(&self, forwarding: &ForwardingTable) -> bool {
+        !self.workers.is_empty() && forwarding.readers().len() >= self.threshold
+    }
+
+    /// Copies the packet for each worker which relays it to its share of the readers.
+    pub fn dispatch(&self, forwarding: &Arc<ForwardingTable>, packet: &PluginRtpPacket) {
+        let buffer = unsafe { std::slice::from_raw_parts(packet.buffer, packet.length as usize) };
+
+        for (shard, worker) in self.workers.iter().enumerate() {
+            let job = Job {
+                forwarding: forwarding.clone(),
+                buffer: buffer.to_vec(),
+                is_video: matches!(packet.video, 1),
+                extensions: copy_extensions(&packet.extensions),
+                dispatched_at: Instant::now(),
+            };
+
+            match worker.try_send(job) {
+                Ok(()) => (),
+                Err(TrySendError::Full(job)) => {
+                    huge!(
+                        "Fan-out worker {} queue is full; dropping packet", shard;
+                        {"rtc_id": forwarding.stream_id()}
+                    );
+
+                    Metrics::observe_fan_out_dropped_packet(shard, job.is_video);
+                }
+                Err(TrySendError::Disconnected(_)) => err!(
+                    "Fan-out worker {} is gone", shard;
+                    {"rtc_id": forwarding.stream_id()}
+                ),
+            }
+        }
+    }
+}
+
+pub struct FanOutWorker {
+    shard: usize,
+    jobs: Receiver<Job>,
+}
+
+impl FanOutWorker {
+    pub fn start(self) {
+        for mut job in self.jobs.iter() {
+            let mut packet = PluginRtpPacket {
+                video: job.is_video as i8,
+                buffer: job.buffer.as_mut_ptr(),
+                length: job.buffer.len() as i16,
+                extensions: copy_extensions(&job.extensions),
+            };
+
+            let header = JanusRtpHeader::extract(&packet);
+
+            for reader in job.forwarding.shard_readers(self.shard) {
+                if reader.receives(job.is_video) {
+                    if let Err(err) = relay_rtp_packet(reader, &mut packet, &header) {
+                        huge!(
+                            "Failed to relay an RTP packet: {}", err;
+                            {"handle_id": reader.id(), "rtc_id": job.forwarding.stream_id()}
+                        );
+                    }
+                }
+            }
+
+            Metrics::observe_fan_out(job.dispatched_at, true);
+        }
+    }
+}
+
+pub fn fan_out(config: &Config) -> (Vec<FanOutWorker>, FanOut) {
+    let mut workers = Vec::with_capacity(config.workers);
+    let mut senders = Vec::with_capacity(config.workers);
+
+    for shard in 0..config.workers {
+        let (tx, rx) = mpsc::sync_channel(config.queue_capacity);
+
+        workers.push(FanOutWorker { shard, jobs: rx });
+
+        senders.push(tx);
+    }
+
+    let fan_out = FanOut {
+        workers: senders,
+        threshold: config.threshold,
+    };
+
+    (workers, fan_out)
+}
+
+pub fn relay_rtp_packet(
+    reader: &RelayReader,
+    packet: &mut PluginRtpPacket,
+    original_header: &JanusRtpHeader,
+) -> Result<()> {
+    reader
+        .state()
+        .switching_context()
+        .update_rtp_packet_header(packet)?;
+
+    janus_callbacks::relay_rtp(reader.session(), packet);
+
+    // Restore original header rewritten by `janus_rtp_header_update`
+    // for the next iteration of the loop.
+    original_header.restore(packet);
+    Ok(())
+}
+
+fn copy_extensions(extensions: &janus_plugin_rtp_extensions) -> janus_plugin_rtp_extensions {
+    janus_plugin_rtp_extensions {
+        audio_level: extensions.audio_level,
+        audio_level_vad: extensions.audio_level_vad,
+        video_rotation: extensions.video_rotation,
+        video_back_camera: extensions.video_back_camera,
+        video_flipped: extensions.video_flipped,
+    }
+}
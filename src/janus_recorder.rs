@@ -1,6 +1,6 @@
 #![allow(non_camel_case_types)]
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_int, c_long, c_uint};
 
 use anyhow::{bail, format_err, Context, Result};
@@ -20,7 +20,7 @@ pub enum Codec {
 }
 
 impl Codec {
-    fn as_str(self) -> &'static str {
+    pub fn as_str(self) -> &'static str {
         match self {
             Self::VP8 => "vp8",
             Self::Opus => "opus",
@@ -58,6 +58,13 @@ impl<'a> JanusRecorder<'a> {
         }
     }
 
+    /// Name of the MJR file within the records dir including the extension added by Janus.
+    pub fn filename(&self) -> String {
+        unsafe { CStr::from_ptr(self.recorder.filename) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
     pub fn close(&mut self) -> Result<()> {
         let res = unsafe { janus_recorder_close(self.recorder) };
 
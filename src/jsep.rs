@@ -7,7 +7,8 @@ use serde_json::Value as JsonValue;
 
 use crate::{
     janus_rtp::{janus_rtp_extmap_audio_level, JANUS_RTP_EXTMAP_AUDIO_LEVEL},
-    switchboard::StreamId,
+    recorder::RecordMedia,
+    switchboard::{StreamId, SwitchboardError},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +29,34 @@ impl Jsep {
         })
     }
 
+    /// Returns media the offer is going to send judging by its m-lines.
+    /// Rejected m-lines with zero port and the ones not sending anything are skipped.
+    pub fn find_sent_media(jsep: &JsonValue) -> Option<RecordMedia> {
+        let sdp = jsep.get("sdp").and_then(|x| x.as_str())?;
+        let mut sections = Vec::new();
+
+        for line in sdp.lines() {
+            if let Some(m_line) = line.strip_prefix("m=") {
+                let mut parts = m_line.split(' ');
+                let media = parts.next().unwrap_or_default();
+                let is_rejected = parts.next() == Some("0");
+                sections.push((media, !is_rejected));
+            } else if line == "a=recvonly" || line == "a=inactive" {
+                if let Some((_, is_sending)) = sections.last_mut() {
+                    *is_sending = false;
+                }
+            }
+        }
+
+        let is_sending = |kind| {
+            sections
+                .iter()
+                .any(|(media, is_sending)| *media == kind && *is_sending)
+        };
+
+        RecordMedia::from_flags(is_sending("audio"), is_sending("video"))
+    }
+
     /// Parses JSEP SDP offer and returns the answer.
     pub fn negotiate(jsep_offer: &JsonValue, stream_id: StreamId) -> Result<Option<Self>> {
         let offer = serde_json::from_value::<Jsep>(jsep_offer.clone())
@@ -55,7 +84,7 @@ impl Jsep {
 
         let video_bitrate = app.switchboard.with_read_lock(|switchboard| {
             let writer_config = switchboard.writer_config(stream_id);
-            Ok(writer_config.video_remb())
+            Ok::<_, SwitchboardError>(writer_config.video_remb())
         })?;
 
         Self::set_publisher_bitrate_constraints(
@@ -119,6 +148,7 @@ mod tests {
     use serde_json::Value;
 
     use super::Jsep;
+    use crate::recorder::RecordMedia;
 
     #[test]
     fn test_get_audio_ext() {
@@ -129,5 +159,25 @@ mod tests {
         let value: Value = serde_json::from_str(jsep).unwrap();
 
         assert_eq!(Jsep::find_audio_ext_id(&value), Some(1));
+        assert_eq!(Jsep::find_sent_media(&value), Some(RecordMedia::Both));
+    }
+
+    #[test]
+    fn test_find_sent_media() {
+        let jsep = |sdp: &str| serde_json::json!({"type": "offer", "sdp": sdp});
+
+        let audio_only = "v=0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 109\r\na=sendonly\r\nm=video 0 UDP/TLS/RTP/SAVPF 120\r\n";
+        assert_eq!(
+            Jsep::find_sent_media(&jsep(audio_only)),
+            Some(RecordMedia::Audio)
+        );
+
+        let video_only = "v=0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 109\r\na=inactive\r\nm=video 9 UDP/TLS/RTP/SAVPF 120\r\n";
+        assert_eq!(
+            Jsep::find_sent_media(&jsep(video_only)),
+            Some(RecordMedia::Video)
+        );
+
+        assert_eq!(Jsep::find_sent_media(&jsep("v=0\r\n")), None);
     }
 }
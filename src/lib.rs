@@ -10,7 +10,7 @@ use std::path::Path;
 use std::slice;
 use std::{
     ffi::{CStr, CString},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context, Result};
@@ -27,6 +27,7 @@ mod utils;
 mod app;
 mod bidirectional_multimap;
 mod conf;
+mod fan_out;
 mod janus_callbacks;
 mod janus_recorder;
 pub mod janus_rtp;
@@ -35,7 +36,9 @@ mod message_handler;
 mod metrics;
 mod recorder;
 mod register;
+mod rtp;
 mod serde;
+mod sharded_map;
 mod switchboard;
 #[cfg(test)]
 mod test_stubs;
@@ -43,12 +46,15 @@ mod test_stubs;
 use app::App;
 use conf::Config;
 use janus_rtp::JanusRtpHeader;
-use switchboard::{SessionId, Switchboard};
+use switchboard::{RelaySession, RelayTable, Session, SessionId, StreamId, SwitchboardError};
 
 use crate::{
+    fan_out::relay_rtp_packet,
     janus_rtp::AudioLevel,
     message_handler::{handle_request, prepare_request, send_response, send_speaking_notification},
     metrics::Metrics,
+    recorder::StreamEvent,
+    rtp::SenderReport,
 };
 
 const INITIAL_REMBS: u64 = 4;
@@ -101,7 +107,7 @@ fn create_session_impl(handle: *mut PluginSession) -> Result<()> {
     let session = unsafe { SessionWrapper::associate(handle, session_id) }
         .context("Session associate error")?;
 
-    app!()?.switchboard.with_write_lock(|mut switchboard| {
+    app!()?.switchboard.with_write_lock(|switchboard| {
         switchboard.insert_new_session(session);
         Ok(())
     })
@@ -176,11 +182,17 @@ extern "C" fn setup_media(handle: *mut PluginSession) {
 
 fn setup_media_impl(handle: *mut PluginSession) -> Result<()> {
     let session_id = session_id(handle)?;
-    app!()?.switchboard.with_read_lock(|switchboard| {
-        if let Some(publisher) = switchboard.publisher_to(session_id) {
-            send_fir(publisher, &switchboard);
-        }
+    let relay_table = app!()?.switchboard.relay_table();
+
+    if let Some(publisher) = relay_table
+        .session(session_id)
+        .ok()
+        .and_then(|session| session.publisher())
+    {
+        send_fir(publisher, &relay_table);
+    }
 
+    app!()?.switchboard.with_read_lock(|switchboard| {
         let rtc_id = switchboard.stream_id_to(session_id);
         info!("WebRTC media is now available"; {"handle_id": session_id, "rtc_id": rtc_id});
         Ok(())
@@ -197,91 +209,151 @@ fn incoming_rtp_impl(handle: *mut PluginSession, packet: *mut PluginRtpPacket) -
     let is_video = matches!(packet.video, 1);
     let header = JanusRtpHeader::extract(packet);
     let session_id = session_id(handle)?;
-    app.switchboard.with_read_lock(|switchboard| {
-        let state = switchboard.state(session_id)?;
-        let is_speaking =
-        app.config.speaking_notifications
-            .as_ref()
-            .filter(|_| !is_video)
-            .and_then(|config| {
-                let agent_id = switchboard.agent_id(session_id)?;
-                let is_speaking = state.is_speaking(AudioLevel::new(packet, state.audio_level_ext_id()?)?,  config)?;
-                Some((agent_id, is_speaking))
-            });
-
-        if let Some((agent_id, is_speaking)) = is_speaking {
-            verb!("Sending speaking notification: is_speaking: {}, agent_id: {}", is_speaking, agent_id);
-            if let Err(err) = send_speaking_notification(&app.janus_sender, session_id, agent_id, is_speaking) {
-                err!("Sending speaking notification errored: {:?}", err; { "session_id": session_id, "agent_id": agent_id });
-            }
+    let mut relay_table = app.switchboard.relay_table();
+
+    // Switch readers over from the previous publisher once they can decode the new one.
+    let takeover_timeout = app.config.switchboard.takeover_keyframe_timeout;
+    let publisher = relay_table.session(session_id)?;
+
+    if let Some(stream_id) = takeover_to_complete(publisher, packet, takeover_timeout) {
+        app.switchboard
+            .with_write_lock(|switchboard| switchboard.complete_takeover(stream_id))?;
+
+        relay_table = app.switchboard.relay_table();
+    }
+
+    let publisher = relay_table.session(session_id)?;
+    let state = publisher.state();
+
+    let is_speaking = app
+        .config
+        .speaking_notifications
+        .as_ref()
+        .filter(|_| !is_video)
+        .and_then(|config| {
+            let agent_id = publisher.agent_id()?;
+            let is_speaking = state.is_speaking(
+                AudioLevel::new(packet, state.audio_level_ext_id()?)?,
+                config,
+            )?;
+            Some((agent_id, is_speaking))
+        });
+
+    if let Some((agent_id, is_speaking)) = is_speaking {
+        verb!(
+            "Sending speaking notification: is_speaking: {}, agent_id: {}",
+            is_speaking,
+            agent_id
+        );
+        if let Err(err) =
+            send_speaking_notification(&app.janus_sender, session_id, agent_id, is_speaking)
+        {
+            err!("Sending speaking notification errored: {:?}", err; { "session_id": session_id, "agent_id": agent_id });
         }
-        // Touch last packet timestamp  to drop timeout.
-        state.touch_last_rtp_packet_timestamp();
-
-        let stream_id = switchboard
-            .published_by(session_id)
-            .ok_or_else(|| anyhow!("Failed to identify the stream id {} of the packet", session_id))?;
-
-        let writer_config = switchboard.writer_config(stream_id);
-
-        // Send incremental initial or regular REMB to the publisher if needed to control bitrate.
-        // Do it only for video because Windows and Linux don't make a difference for media types
-        // and apply audio limitation to video while only MacOS does.
-        let remb_interval = chrono::Duration::seconds(5);
-        if is_video {
-            let now = Utc::now();
-            if now - state.last_fir_timestamp() >= app.fir_interval {
-                send_fir(session_id, &switchboard);
-            }
-            let target_bitrate = writer_config.video_remb();
-            let initial_rembs_left = INITIAL_REMBS - state.initial_rembs_counter();
 
-            if initial_rembs_left > 0 {
-                let bitrate = target_bitrate / initial_rembs_left as u32;
-                send_remb(session_id, bitrate);
+        if let Some(recorder) = state.recorder() {
+            let event = StreamEvent::SpeakingChange {
+                agent_id: agent_id.to_owned(),
+                is_speaking,
+            };
+
+            if let Err(err) = recorder.record_event(event) {
+                err!("Failed to record speaking change: {:?}", err; { "session_id": session_id, "agent_id": agent_id });
+            }
+        }
+    }
+    // Touch last packet timestamp  to drop timeout.
+    state.touch_last_rtp_packet_timestamp();
+
+    let forwarding = publisher.forwarding().ok_or_else(|| {
+        anyhow!(
+            "Failed to identify the stream id {} of the packet",
+            session_id
+        )
+    })?;
+
+    let stream_id = forwarding.stream_id();
+
+    // Send incremental initial or regular REMB to the publisher if needed to control bitrate.
+    // Do it only for video because Windows and Linux don't make a difference for media types
+    // and apply audio limitation to video while only MacOS does.
+    let remb_interval = chrono::Duration::seconds(5);
+    if is_video {
+        let now = Utc::now();
+        if now - state.last_fir_timestamp() >= app.fir_interval {
+            send_fir(session_id, &relay_table);
+        }
+        let target_bitrate = forwarding.writer_config().video_remb();
+        let initial_rembs_left = INITIAL_REMBS - state.initial_rembs_counter();
+
+        if initial_rembs_left > 0 {
+            let bitrate = target_bitrate / initial_rembs_left as u32;
+            send_remb(publisher.session(), session_id, bitrate);
+            state.touch_last_remb_timestamp();
+            state.increment_initial_rembs_counter();
+        } else if let Some(last_remb_timestamp) = state.last_remb_timestamp() {
+            if now - last_remb_timestamp >= remb_interval {
+                send_remb(publisher.session(), session_id, target_bitrate);
                 state.touch_last_remb_timestamp();
-                state.increment_initial_rembs_counter();
-            } else if let Some(last_remb_timestamp) = state.last_remb_timestamp() {
-                if now - last_remb_timestamp >= remb_interval {
-                    send_remb(session_id, target_bitrate);
-                    state.touch_last_remb_timestamp();
-                }
             }
         }
+    }
+
+    // Retransmit packet to publishers as is.
+    if app.fan_out.is_parallel(forwarding) {
+        app.fan_out.dispatch(forwarding, packet);
+    } else {
+        let fan_out_start = Instant::now();
 
-        // Retransmit packet to publishers as is.
-        for subscriber_id in switchboard.subscribers_to(session_id) {
+        for reader in forwarding.readers() {
             // Check whether media is muted by the agent.
-            let is_relay_packet = switchboard
-                .reader_config(stream_id, subscriber_id)
-                .map(|reader_config| match is_video {
-                    true => reader_config.receive_video(),
-                    false => reader_config.receive_audio(),
-                })
-                .unwrap_or(true);
-
-            if is_relay_packet {
-                match relay_rtp_packet(&switchboard, *subscriber_id, packet, &header) {
+            if reader.receives(is_video) {
+                match relay_rtp_packet(reader, packet, &header) {
                     Ok(()) => (),
                     Err(err) => huge!(
                         "Failed to relay an RTP packet: {}", err;
-                        {"handle_id": subscriber_id, "rtc_id": stream_id}
+                        {"handle_id": reader.id(), "rtc_id": stream_id}
                     ),
                 }
             }
         }
 
-        // Push packet to the recorder.
-        if let Some(recorder) = state.recorder() {
-            let buf = unsafe {
-                std::slice::from_raw_parts(packet.buffer as *const i8, packet.length as usize)
-            };
+        Metrics::observe_fan_out(fan_out_start, false);
+    }
 
-            recorder.record_packet(buf, is_video)?;
-        }
+    // Push packet to the recorder.
+    if let Some(recorder) = state.recorder() {
+        let buf = unsafe {
+            std::slice::from_raw_parts(packet.buffer as *const i8, packet.length as usize)
+        };
 
-        Ok(())
-    })
+        recorder.record_packet(buf, is_video)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the stream which readers should be switched to the publisher sending the packet.
+fn takeover_to_complete(
+    publisher: &RelaySession,
+    packet: &PluginRtpPacket,
+    timeout: Duration,
+) -> Option<StreamId> {
+    let since = publisher.takeover_since()?;
+
+    let is_keyframe = matches!(packet.video, 1) && {
+        let buf =
+            unsafe { slice::from_raw_parts(packet.buffer as *const u8, packet.length as usize) };
+        rtp::is_vp8_keyframe(buf)
+    };
+
+    if is_keyframe || since.elapsed() >= timeout {
+        publisher
+            .forwarding()
+            .map(|forwarding| forwarding.stream_id())
+    } else {
+        None
+    }
 }
 
 extern "C" fn incoming_rtcp(handle: *mut PluginSession, packet: *mut PluginRtcpPacket) {
@@ -292,30 +364,51 @@ fn incoming_rtcp_impl(handle: *mut PluginSession, packet: *mut PluginRtcpPacket)
     let session_id = session_id(handle)?;
     let packet = unsafe { &mut *packet };
     let data = unsafe { slice::from_raw_parts_mut(packet.buffer, packet.length as usize) };
+    let relay_table = app!()?.switchboard.relay_table();
 
-    app!()?.switchboard.with_read_lock(|switchboard| {
-        match packet.video {
-            1 if janus::rtcp::has_pli(data) => {
-                if let Some(publisher) = switchboard.publisher_to(session_id) {
-                    send_pli(publisher, &switchboard);
-                }
+    let session = match relay_table.session(session_id) {
+        Ok(session) => session,
+        // The session hasn't joined any stream yet.
+        Err(_) => return Ok(()),
+    };
+
+    // Readers are only known to be alive by the RTCP they send.
+    session.state().touch_last_rtcp_packet_timestamp();
+
+    // Keep publisher's NTP ↔ RTP timestamps mapping along with the record
+    // to synchronize tracks in post-processing.
+    if let Some(recorder) = session.state().recorder() {
+        let buf =
+            unsafe { slice::from_raw_parts(packet.buffer as *const u8, packet.length as usize) };
+
+        if let Some(report) = SenderReport::parse(buf) {
+            if let Err(err) = recorder.record_sender_report(report, matches!(packet.video, 1)) {
+                err!("Failed to record sender report: {:?}", err; { "session_id": session_id });
             }
-            1 if janus::rtcp::has_fir(data) => {
-                if let Some(publisher) = switchboard.publisher_to(session_id) {
-                    send_fir(publisher, &switchboard);
-                }
+        }
+    }
+
+    match packet.video {
+        1 if janus::rtcp::has_pli(data) => {
+            if let Some(publisher) = session.publisher() {
+                send_pli(publisher, &relay_table);
+            }
+        }
+        1 if janus::rtcp::has_fir(data) => {
+            if let Some(publisher) = session.publisher() {
+                send_fir(publisher, &relay_table);
             }
-            _ => {
-                for subscriber in switchboard.subscribers_to(session_id) {
-                    let subscriber_session = switchboard.session(*subscriber)?;
+        }
+        _ => {
+            let readers = session.forwarding().map(|f| f.readers()).unwrap_or(&[]);
 
-                    janus_callbacks::relay_rtcp(subscriber_session, packet);
-                }
+            for reader in readers {
+                janus_callbacks::relay_rtcp(reader.session(), packet);
             }
         }
+    }
 
-        Ok(())
-    })
+    Ok(())
 }
 
 extern "C" fn incoming_data(_handle: *mut PluginSession, _packet: *mut PluginDataPacket) {
@@ -338,7 +431,7 @@ fn hangup_media_impl(handle: *mut PluginSession) -> Result<()> {
     app!()?.switchboard.with_read_lock(|switchboard| {
         let rtc_id = switchboard.stream_id_to(session_id);
         info!("Hang up"; {"handle_id": session_id, "rtc_id": rtc_id});
-        switchboard.disconnect(session_id)
+        Ok(switchboard.disconnect(session_id)?)
     })
 }
 
@@ -349,15 +442,15 @@ extern "C" fn destroy_session(handle: *mut PluginSession, error: *mut c_int) {
 fn destroy_session_impl(handle: *mut PluginSession, _error: *mut c_int) -> Result<()> {
     let session_id = session_id(handle)?;
 
-    let rtc_id = app!()?
-        .switchboard
-        .with_read_lock(|switchboard| Ok(switchboard.stream_id_to(session_id)))?;
+    let rtc_id = app!()?.switchboard.with_read_lock(|switchboard| {
+        Ok::<_, SwitchboardError>(switchboard.stream_id_to(session_id))
+    })?;
 
     info!("Handle destroyed"; {"handle_id": session_id, "rtc_id": rtc_id});
 
     app!()?
         .switchboard
-        .with_write_lock(|mut switchboard| switchboard.handle_disconnect(session_id))
+        .with_write_lock(|switchboard| Ok(switchboard.handle_disconnect(session_id)?))
 }
 
 extern "C" fn destroy() {
@@ -373,34 +466,12 @@ fn session_id(handle: *mut PluginSession) -> Result<SessionId> {
     }
 }
 
-fn relay_rtp_packet(
-    switchboard: &Switchboard,
-    reader: SessionId,
-    packet: &mut PluginRtpPacket,
-    original_header: &JanusRtpHeader,
-) -> Result<()> {
-    let reader_state = switchboard.state(reader)?;
-
-    reader_state
-        .switching_context()
-        .update_rtp_packet_header(packet)?;
-
-    let reader_session = switchboard.session(reader)?;
-
-    janus_callbacks::relay_rtp(reader_session, packet);
-
-    // Restore original header rewritten by `janus_rtp_header_update`
-    // for the next iteration of the loop.
-    original_header.restore(packet);
-    Ok(())
-}
-
-fn send_pli(publisher: SessionId, switchboard: &Switchboard) {
-    report_error(send_pli_impl(publisher, switchboard));
+fn send_pli(publisher: SessionId, relay_table: &RelayTable) {
+    report_error(send_pli_impl(publisher, relay_table));
 }
 
-fn send_pli_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()> {
-    let session = switchboard.session(publisher)?;
+fn send_pli_impl(publisher: SessionId, relay_table: &RelayTable) -> Result<()> {
+    let session = relay_table.session(publisher)?.session();
 
     let mut pli = janus::rtcp::gen_pli();
 
@@ -414,14 +485,14 @@ fn send_pli_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()>
     Ok(())
 }
 
-fn send_fir(publisher: SessionId, switchboard: &Switchboard) {
-    report_error(send_fir_impl(publisher, switchboard));
+fn send_fir(publisher: SessionId, relay_table: &RelayTable) {
+    report_error(send_fir_impl(publisher, relay_table));
 }
 
-fn send_fir_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()> {
-    let session = switchboard.session(publisher)?;
+fn send_fir_impl(publisher: SessionId, relay_table: &RelayTable) -> Result<()> {
+    let relay_session = relay_table.session(publisher)?;
 
-    let state = switchboard.state(publisher)?;
+    let state = relay_session.state();
     state.touch_last_fir_timestamp();
     let mut seq = state.increment_fir_seq();
     let mut fir = janus::rtcp::gen_fir(&mut seq);
@@ -432,30 +503,22 @@ fn send_fir_impl(publisher: SessionId, switchboard: &Switchboard) -> Result<()>
         length: fir.len() as i16,
     };
 
-    janus_callbacks::relay_rtcp(session, &mut packet);
+    janus_callbacks::relay_rtcp(relay_session.session(), &mut packet);
     Ok(())
 }
 
-fn send_remb(publisher: SessionId, bitrate: u32) {
+fn send_remb(session: &Session, publisher: SessionId, bitrate: u32) {
     verb!("Sending REMB bitrate = {}", bitrate; {"handle_id": publisher});
-    report_error(send_remb_impl(publisher, bitrate));
-}
-
-fn send_remb_impl(publisher: SessionId, bitrate: u32) -> Result<()> {
-    app!()?.switchboard.with_read_lock(move |switchboard| {
-        let session = switchboard.session(publisher)?;
 
-        let mut remb = janus::rtcp::gen_remb(bitrate);
+    let mut remb = janus::rtcp::gen_remb(bitrate);
 
-        let mut packet = PluginRtcpPacket {
-            video: 1,
-            buffer: remb.as_mut_ptr(),
-            length: remb.len() as i16,
-        };
+    let mut packet = PluginRtcpPacket {
+        video: 1,
+        buffer: remb.as_mut_ptr(),
+        length: remb.len() as i16,
+    };
 
-        janus_callbacks::relay_rtcp(session, &mut packet);
-        Ok(())
-    })
+    janus_callbacks::relay_rtcp(session, &mut packet);
 }
 
 fn report_error(res: Result<()>) {
@@ -496,3 +559,92 @@ static PLUGIN: Plugin = build_plugin!(
 );
 
 export_plugin!(&PLUGIN);
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use janus::PluginRtpPacket;
+    use janus_plugin_sys::plugin::janus_plugin_rtp_extensions;
+    use uuid::Uuid;
+
+    use super::takeover_to_complete;
+    use crate::{
+        conf::SwitchboardConfig,
+        switchboard::{LockedSwitchboard, SessionId, SwitchboardError, WriterConfig},
+        test_stubs::session,
+    };
+
+    fn video_packet(payload: &[u8]) -> Vec<i8> {
+        let mut packet = vec![
+            0x80, 0x60, 0x12, 0x34, 0x00, 0x00, 0x10, 0x00, 0xde, 0xad, 0xbe, 0xef,
+        ];
+
+        packet.extend_from_slice(payload);
+        packet.into_iter().map(|byte| byte as i8).collect()
+    }
+
+    #[test]
+    fn complete_takeover_on_keyframe_or_timeout() {
+        let config: SwitchboardConfig =
+            serde_json::from_value(serde_json::json!({})).expect("Failed to parse config");
+
+        let switchboard = LockedSwitchboard::new(config);
+        let stream_id = Uuid::new_v4();
+
+        switchboard
+            .with_write_lock(|switchboard| {
+                let agent_id = String::from("web.john.usr.example.org");
+
+                for (publisher, reader) in [(1, 2), (3, 4)] {
+                    switchboard.insert_new_session(session(publisher));
+                    let publisher = SessionId::new(publisher);
+                    switchboard.create_stream(stream_id, publisher, agent_id.clone(), None)?;
+
+                    let writer_config = WriterConfig::with_video_remb(1_000_000);
+                    switchboard.set_writer_config(stream_id, writer_config);
+                    switchboard.insert_new_session(session(reader));
+                    let reader_id = format!("web.reader{}.usr.example.org", reader);
+                    switchboard.join_stream(stream_id, SessionId::new(reader), reader_id)?;
+                }
+
+                Ok::<_, SwitchboardError>(())
+            })
+            .expect("Failed to start takeover");
+
+        let relay_table = switchboard.relay_table();
+        let old_publisher = relay_table.session(SessionId::new(1)).unwrap();
+        let new_publisher = relay_table.session(SessionId::new(3)).unwrap();
+        let timeout = Duration::from_secs(2);
+
+        let check = |publisher, payload: &[u8], timeout| {
+            let mut buffer = video_packet(payload);
+
+            let packet = PluginRtpPacket {
+                video: 1,
+                buffer: buffer.as_mut_ptr(),
+                length: buffer.len() as i16,
+                extensions: janus_plugin_rtp_extensions {
+                    audio_level: -1,
+                    audio_level_vad: 0,
+                    video_rotation: -1,
+                    video_back_camera: 0,
+                    video_flipped: 0,
+                },
+            };
+
+            takeover_to_complete(publisher, &packet, timeout)
+        };
+
+        assert_eq!(check(new_publisher, &[0x10, 0x01], timeout), None);
+        assert_eq!(
+            check(new_publisher, &[0x10, 0x00], timeout),
+            Some(stream_id)
+        );
+        assert_eq!(
+            check(new_publisher, &[0x10, 0x01], Duration::ZERO),
+            Some(stream_id)
+        );
+        assert_eq!(check(old_publisher, &[0x10, 0x00], timeout), None);
+    }
+}
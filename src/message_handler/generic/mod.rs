@@ -15,7 +15,7 @@ use crate::utils;
 use crate::{jsep::Jsep, message_handler::Method};
 use crate::{
     message_handler::generic::response::Payload,
-    switchboard::{AgentId, SessionId, StreamId},
+    switchboard::{AgentId, SessionId, StreamId, StreamState},
 };
 
 pub use self::operation::{MethodKind, Operation, Result as OperationResult};
@@ -148,6 +148,43 @@ pub fn send_speaking_notification(
     Ok(())
 }
 
+pub fn send_stream_state_notification(
+    sender: &JanusSender,
+    session_id: SessionId,
+    stream_id: StreamId,
+    state: StreamState,
+) -> anyhow::Result<()> {
+    let notification = serde_json::json!({
+        "stream_id": stream_id,
+        "state": state
+    });
+    let response = Some(JanssonValue::try_from(
+        &Payload::new(StatusCode::OK).set_response(notification),
+    )?);
+
+    let stream_state_b64enc = "{\"kind\":\"IlN0cmVhbVN0YXRlIg==\"}";
+    sender.send(session_id, stream_state_b64enc, response, None)?;
+    Ok(())
+}
+
+pub fn send_session_evicted_notification(
+    sender: &JanusSender,
+    session_id: SessionId,
+    agent_id: &AgentId,
+) -> anyhow::Result<()> {
+    let notification = serde_json::json!({
+        "agent_id": agent_id,
+        "reason": "too_many_sessions"
+    });
+    let response = Some(JanssonValue::try_from(
+        &Payload::new(StatusCode::OK).set_response(notification),
+    )?);
+
+    let session_evicted_b64enc = "{\"kind\":\"IlNlc3Npb25FdmljdGVkIg==\"}";
+    sender.send(session_id, session_evicted_b64enc, response, None)?;
+    Ok(())
+}
+
 fn notify_error(err: &SvcError) {
     if err.status_code() == StatusCode::INTERNAL_SERVER_ERROR {
         huge!("Sending error to Sentry");
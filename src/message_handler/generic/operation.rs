@@ -21,6 +21,8 @@ pub trait Operation: fmt::Debug + Send + Sync {
 pub enum MethodKind {
     AgentLeave,
     ReaderConfigUpdate,
+    RecordingDelete,
+    RecordingList,
     StreamCreate,
     StreamRead,
     StreamUpload,
@@ -12,8 +12,9 @@ use crate::janus_callbacks;
 use crate::switchboard::SessionId;
 
 pub use self::generic::{
-    handle_request, prepare_request, send_response, send_speaking_notification, MethodKind,
-    Operation, OperationResult, PreparedRequest, Request,
+    handle_request, prepare_request, send_response, send_session_evicted_notification,
+    send_speaking_notification, send_stream_state_notification, MethodKind, Operation,
+    OperationResult, PreparedRequest, Request,
 };
 
 #[derive(Debug, Clone, Deserialize)]
@@ -23,6 +24,10 @@ pub enum Method {
     AgentLeave(operations::agent_leave::Request),
     #[serde(rename = "reader_config.update")]
     ReaderConfigUpdate(operations::reader_config_update::Request),
+    #[serde(rename = "recording.delete")]
+    RecordingDelete(operations::recording_delete::Request),
+    #[serde(rename = "recording.list")]
+    RecordingList(operations::recording_list::Request),
     #[serde(rename = "stream.create")]
     StreamCreate(operations::stream_create::Request),
     #[serde(rename = "stream.read")]
@@ -41,6 +46,8 @@ impl Operation for Method {
         match self {
             Method::AgentLeave(x) => x.call(request).await,
             Method::ReaderConfigUpdate(x) => x.call(request).await,
+            Method::RecordingDelete(x) => x.call(request).await,
+            Method::RecordingList(x) => x.call(request).await,
             Method::StreamCreate(x) => x.call(request).await,
             Method::StreamRead(x) => x.call(request).await,
             Method::StreamUpload(x) => x.call(request).await,
@@ -53,6 +60,8 @@ impl Operation for Method {
         match self {
             Method::AgentLeave(x) => x.stream_id(),
             Method::ReaderConfigUpdate(x) => x.stream_id(),
+            Method::RecordingDelete(x) => x.stream_id(),
+            Method::RecordingList(x) => x.stream_id(),
             Method::StreamCreate(x) => x.stream_id(),
             Method::StreamRead(x) => x.stream_id(),
             Method::StreamUpload(x) => x.stream_id(),
@@ -65,6 +74,8 @@ impl Operation for Method {
         match self {
             Method::AgentLeave(x) => x.method_kind(),
             Method::ReaderConfigUpdate(x) => x.method_kind(),
+            Method::RecordingDelete(x) => x.method_kind(),
+            Method::RecordingList(x) => x.method_kind(),
             Method::StreamCreate(x) => x.method_kind(),
             Method::StreamRead(x) => x.method_kind(),
             Method::StreamUpload(x) => x.method_kind(),
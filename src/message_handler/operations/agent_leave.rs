@@ -3,7 +3,7 @@ use async_trait::async_trait;
 use http::StatusCode;
 use svc_error::Error as SvcError;
 
-use crate::switchboard::{AgentId, StreamId};
+use crate::switchboard::{AgentId, StreamId, SwitchboardError};
 use crate::{janus_callbacks, message_handler::generic::MethodKind};
 
 #[derive(Clone, Debug, Deserialize)]
@@ -42,9 +42,8 @@ impl super::Operation for Request {
                     janus_callbacks::end_session(session);
                 }
 
-                Ok(())
-            })
-            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+                Ok::<_, SwitchboardError>(())
+            })?;
 
         Ok(Response {}.into())
     }
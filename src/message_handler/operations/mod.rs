@@ -1,9 +1,28 @@
+use svc_error::Error as SvcError;
+
+use crate::switchboard::SwitchboardError;
+
 pub use super::{Operation, OperationResult, Request};
 
 pub mod agent_leave;
 pub mod reader_config_update;
+pub mod recording_delete;
+pub mod recording_list;
 pub mod service_ping;
 pub mod stream_create;
 pub mod stream_read;
 pub mod stream_upload;
 pub mod writer_config_update;
+
+/// Responds with the stable error type and status of the switchboard failure.
+impl From<SwitchboardError> for SvcError {
+    fn from(err: SwitchboardError) -> Self {
+        let (kind, title) = err.kind();
+
+        SvcError::builder()
+            .kind(kind, title)
+            .status(err.status())
+            .detail(&err.to_string())
+            .build()
+    }
+}
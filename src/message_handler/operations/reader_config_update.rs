@@ -5,7 +5,8 @@ use svc_error::Error as SvcError;
 
 use crate::{
     message_handler::generic::MethodKind,
-    switchboard::{AgentId, ReaderConfig, StreamId},
+    recorder::StreamEvent,
+    switchboard::{AgentId, ReaderConfig, StreamId, SwitchboardError},
 };
 
 #[derive(Clone, Debug, Deserialize)]
@@ -31,19 +32,25 @@ impl super::Operation for Request {
 
         let app = app!().map_err(internal_error)?;
 
-        app.switchboard
-            .with_write_lock(|mut switchboard| {
-                for config_item in &self.configs {
-                    switchboard.update_reader_config(
-                        config_item.stream_id,
-                        &config_item.reader_id,
-                        ReaderConfig::new(config_item.receive_video, config_item.receive_audio),
-                    );
-                }
+        app.switchboard.with_write_lock(|switchboard| {
+            for config_item in &self.configs {
+                switchboard.update_reader_config(
+                    config_item.stream_id,
+                    &config_item.reader_id,
+                    ReaderConfig::new(config_item.receive_video, config_item.receive_audio),
+                );
 
-                Ok(())
-            })
-            .map_err(internal_error)?;
+                let event = StreamEvent::ReaderConfigUpdate {
+                    reader_id: config_item.reader_id.clone(),
+                    receive_video: config_item.receive_video,
+                    receive_audio: config_item.receive_audio,
+                };
+
+                switchboard.record_event(config_item.stream_id, event);
+            }
+
+            Ok::<_, SwitchboardError>(())
+        })?;
 
         Ok(Response {}.into())
     }
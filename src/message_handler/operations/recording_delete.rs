@@ -0,0 +1,81 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::{
+    message_handler::generic::MethodKind,
+    switchboard::{StreamId, SwitchboardError},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {
+    id: StreamId,
+}
+
+#[derive(Serialize)]
+struct Response {}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.delete operation"; {"rtc_id": self.id});
+        let app = app!().map_err(internal_error)?;
+
+        let is_recording = app.switchboard.with_read_lock(|switchboard| {
+            Ok::<_, SwitchboardError>(switchboard.stream_recorder(self.id).is_some())
+        })?;
+
+        if is_recording {
+            let err = anyhow!("The stream is being recorded");
+            return Err(error(StatusCode::CONFLICT, err));
+        }
+
+        // The recorder may still be closing the last segment of a stream just stopped.
+        let recorder = app.recorders_creator.new_handle(self.id);
+        recorder.wait_stop().await.map_err(internal_error)?;
+
+        recorder
+            .check_existence()
+            .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
+
+        if recorder.is_uploading().await {
+            let err = anyhow!("The record is being uploaded");
+            return Err(error(StatusCode::CONFLICT, err));
+        }
+
+        if recorder.is_recovering() {
+            let err = anyhow!("The record is being recovered");
+            return Err(error(StatusCode::CONFLICT, err));
+        }
+
+        if !recorder.is_deletion_enabled() {
+            let err = anyhow!("Records deletion is disabled");
+            return Err(error(StatusCode::FORBIDDEN, err));
+        }
+
+        recorder.delete_record().map_err(internal_error)?;
+        info!("Record deleted"; {"rtc_id": self.id});
+        Ok(Response {}.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingDelete)
+    }
+}
+
+fn error(status: StatusCode, err: Error) -> SvcError {
+    SvcError::builder()
+        .kind("recording_delete_error", "Error deleting a recording")
+        .status(status)
+        .detail(&err.to_string())
+        .build()
+}
+
+fn internal_error(err: Error) -> SvcError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, err)
+}
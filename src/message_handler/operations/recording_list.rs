@@ -0,0 +1,59 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use http::StatusCode;
+use svc_error::Error as SvcError;
+
+use crate::{
+    message_handler::generic::MethodKind,
+    recorder::{list_records, RecordInfo},
+    switchboard::{StreamId, SwitchboardError},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Request {}
+
+#[derive(Serialize)]
+struct Response {
+    recordings: Vec<RecordInfo>,
+}
+
+#[async_trait]
+impl super::Operation for Request {
+    async fn call(&self, _request: &super::Request) -> super::OperationResult {
+        verb!("Calling recording.list operation");
+        let app = app!().map_err(internal_error)?;
+
+        let uploading_streams = app.recorders_creator.uploader().uploading_streams();
+
+        let is_recording = |stream_id| {
+            app.switchboard
+                .with_read_lock(|switchboard| {
+                    Ok::<_, SwitchboardError>(switchboard.stream_recorder(stream_id).is_some())
+                })
+                .unwrap_or(false)
+        };
+
+        let volumes = app.recorders_creator.volumes().paths();
+
+        let recordings = list_records(volumes, is_recording, |id| uploading_streams.contains(&id))
+            .map_err(internal_error)?;
+
+        Ok(Response { recordings }.into())
+    }
+
+    fn stream_id(&self) -> Option<StreamId> {
+        None
+    }
+
+    fn method_kind(&self) -> Option<MethodKind> {
+        Some(MethodKind::RecordingList)
+    }
+}
+
+fn internal_error(err: Error) -> SvcError {
+    SvcError::builder()
+        .kind("recording_list_error", "Error listing recordings")
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .detail(&err.to_string())
+        .build()
+}
@@ -3,7 +3,10 @@ use async_trait::async_trait;
 use http::StatusCode;
 use svc_error::Error as SvcError;
 
-use crate::{message_handler::generic::MethodKind, switchboard::StreamId};
+use crate::{
+    message_handler::generic::MethodKind,
+    switchboard::{StreamId, SwitchboardError},
+};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Request {}
@@ -16,12 +19,10 @@ impl super::Operation for Request {
     async fn call(&self, request: &super::Request) -> super::OperationResult {
         let app = app!().map_err(internal_error)?;
 
-        app.switchboard
-            .with_write_lock(|mut switchboard| {
-                switchboard.touch_session(request.session_id());
-                Ok(())
-            })
-            .map_err(internal_error)?;
+        app.switchboard.with_write_lock(|switchboard| {
+            switchboard.touch_session(request.session_id());
+            Ok::<_, SwitchboardError>(())
+        })?;
 
         Ok(Response {}.into())
     }
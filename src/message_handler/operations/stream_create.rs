@@ -4,8 +4,10 @@ use http::StatusCode;
 use svc_error::Error as SvcError;
 
 use crate::{
+    jsep::Jsep,
     message_handler::generic::MethodKind,
-    switchboard::{AgentId, StreamId},
+    recorder::{LiveUpload, RecordMedia},
+    switchboard::{AgentId, ReaderLimits, StreamId, SwitchboardError, TakeoverPolicy},
 };
 
 #[derive(Clone, Debug, Deserialize)]
@@ -14,6 +16,20 @@ pub struct Request {
     agent_id: AgentId,
     writer_config: Option<WriterConfig>,
     reader_configs: Option<Vec<ReaderConfig>>,
+    #[serde(default)]
+    live_upload: Option<LiveUpload>,
+    /// Media to record. Detected from the SDP offer if not specified.
+    #[serde(default)]
+    record_media: Option<RecordMedia>,
+    /// Overrides the configured takeover policy for this stream.
+    #[serde(default)]
+    takeover_policy: Option<TakeoverPolicy>,
+    /// Maximum number of agents reading the stream. Unlimited if not specified.
+    #[serde(default)]
+    max_readers: Option<usize>,
+    /// Privileged agents to keep reader seats for.
+    #[serde(default)]
+    reserved_readers: Vec<AgentId>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -48,14 +64,68 @@ impl super::Operation for Request {
         };
 
         let app = app!().map_err(internal_error)?;
-        app.switchboard.with_write_lock(|mut switchboard| {
-            switchboard.create_stream(self.id, request.session_id(), self.agent_id.to_owned())?;
-            let mut start_recording = || {
+
+        if let Some(live_upload) = &self.live_upload {
+            if !app.config.upload.backends.contains(&live_upload.backend) {
+                return Err(SvcError::builder()
+                    .kind("stream_create_error", "Error creating a stream")
+                    .status(StatusCode::BAD_REQUEST)
+                    .detail(&format!("Unknown backend '{}'", live_upload.backend))
+                    .build());
+            }
+        }
+
+        if let (true, Some(min_free_space)) = (
+            app.config.recordings.enabled,
+            app.config.recordings.min_free_space,
+        ) {
+            let free_space = app
+                .recorders_creator
+                .volumes()
+                .free_space_for(self.id)
+                .map_err(internal_error)?;
+
+            if free_space < min_free_space {
+                err!(
+                    "Not enough disk space to record: {} bytes free, {} required", free_space, min_free_space;
+                    {"rtc_id": self.id}
+                );
+
+                return Err(SvcError::builder()
+                    .kind("stream_create_error", "Error creating a stream")
+                    .status(StatusCode::INSUFFICIENT_STORAGE)
+                    .detail(&format!(
+                        "Not enough disk space to record: {} bytes free, {} required",
+                        free_space, min_free_space
+                    ))
+                    .build());
+            }
+        }
+
+        let record_media = self
+            .record_media
+            .or_else(|| request.jsep_offer().and_then(Jsep::find_sent_media))
+            .unwrap_or_default();
+
+        app.switchboard.with_write_lock(|switchboard| {
+            switchboard.create_stream(
+                self.id,
+                request.session_id(),
+                self.agent_id.to_owned(),
+                self.takeover_policy,
+            )?;
+
+            if let Some(max_readers) = self.max_readers {
+                let limits = ReaderLimits::new(max_readers, self.reserved_readers.clone());
+                switchboard.set_reader_limits(self.id, limits);
+            }
+
+            let start_recording = || -> Result<(), Error> {
                 if app.config.recordings.enabled {
                     let recorder = app.recorders_creator.new_handle(self.id);
-                    recorder.start_recording()?;
+                    recorder.start_recording(self.live_upload.clone(), record_media)?;
                     verb!("Attaching recorder"; {"handle_id": request.session_id()});
-                    let session_state = switchboard.state_mut(request.session_id())?;
+                    let session_state = switchboard.state(request.session_id())?;
                     session_state.set_recorder(recorder);
                     session_state.set_audio_level_ext_id(request.audio_level_ext_id());
                 }
@@ -63,21 +133,20 @@ impl super::Operation for Request {
                 Ok(())
             };
 
-            start_recording().or_else(|err: Error| {
+            start_recording().or_else(|err| {
                 err!("Failed to start recording; stopping the stream"; {"rtc_id": self.id});
 
-                switchboard
-                    .remove_stream(self.id)
-                    .map_err(|remove_err| {
-                        format_err!(
-                            "Failed to remove stream {}: {} while recovering from another error: {}",
-                            self.id, remove_err, err
-                        )
-                    })?;
-                Ok(())
+                switchboard.remove_stream(self.id).map_err(|remove_err| {
+                    SwitchboardError::Recorder(format_err!(
+                        "Failed to remove stream {}: {} while recovering from another error: {}",
+                        self.id,
+                        remove_err,
+                        err
+                    ))
+                })?;
+                Ok::<_, SwitchboardError>(())
             })
-        })
-        .map_err(internal_error)?;
+        })?;
         if let Some(config) = &self.writer_config {
             let config_item = super::writer_config_update::ConfigItem {
                 stream_id: self.id,
@@ -5,7 +5,7 @@ use svc_error::Error as SvcError;
 
 use crate::{
     message_handler::generic::MethodKind,
-    switchboard::{AgentId, JoinStreamError, StreamId},
+    switchboard::{AgentId, StreamId},
 };
 
 use super::stream_create::ReaderConfig;
@@ -26,38 +26,12 @@ impl super::Operation for Request {
     async fn call(&self, request: &super::Request) -> super::OperationResult {
         verb!("Calling stream.read operation"; {"rtc_id": self.id});
 
-        let error = |status: StatusCode, err: Error| {
-            SvcError::builder()
-                .kind("stream_read_error", "Error reading a stream")
-                .status(status)
-                .detail(&err.to_string())
-                .build()
-        };
-
         app!()
-            .map_err(|err| error(StatusCode::INTERNAL_SERVER_ERROR, err))?
+            .map_err(internal_error)?
             .switchboard
-            .with_write_lock(|mut switchboard| {
-                // this wrapped in `Ok` to avoid using anyhow so we can distingiush
-                // different error types
-                // TODO: refactor `with_write_lock` to allow custom error types
-                Ok(switchboard
-                    .join_stream(self.id, request.session_id(), self.agent_id.to_owned())
-                    .map_err(|e| match e {
-                        JoinStreamError::StreamNotFound => error(
-                            StatusCode::NOT_FOUND,
-                            anyhow!("Stream {} does not exist", self.id),
-                        ),
-                        JoinStreamError::SessionNotFound => {
-                            error(StatusCode::NOT_FOUND, anyhow!("Session does not exist"))
-                        }
-                        JoinStreamError::TooManyAgents => error(
-                            StatusCode::SERVICE_UNAVAILABLE,
-                            anyhow!("Too many agents on server"),
-                        ),
-                    }))
-            })
-            .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))??;
+            .with_write_lock(|switchboard| {
+                switchboard.join_stream(self.id, request.session_id(), self.agent_id.to_owned())
+            })?;
 
         if let Some(configs) = &self.reader_configs {
             let configs = configs
@@ -85,3 +59,11 @@ impl super::Operation for Request {
         Some(MethodKind::StreamRead)
     }
 }
+
+fn internal_error(err: Error) -> SvcError {
+    SvcError::builder()
+        .kind("stream_read_error", "Error reading a stream")
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .detail(&err.to_string())
+        .build()
+}
@@ -1,15 +1,20 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 
-use anyhow::{format_err, Context, Error, Result};
+use anyhow::{format_err, Error, Result};
 use async_std::{process::Command, sync::Mutex};
 use async_trait::async_trait;
 use http::StatusCode;
 use once_cell::sync::Lazy;
+use serde_json::Value as JsonValue;
 use svc_error::Error as SvcError;
 
-use crate::switchboard::StreamId;
-use crate::{message_handler::generic::MethodKind, recorder::RecorderHandle};
+use crate::switchboard::{StreamId, SwitchboardError};
+use crate::{
+    message_handler::generic::MethodKind,
+    recorder::{upload_script_path, RecorderHandle, EVENTS_FILENAME, MANIFEST_FILENAME},
+};
 
 static MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
@@ -24,6 +29,8 @@ pub struct Request {
 struct Response {
     id: StreamId,
     mjr_dumps_uris: Vec<String>,
+    manifest: Option<JsonValue>,
+    events: Vec<JsonValue>,
 }
 
 #[async_trait]
@@ -44,7 +51,7 @@ impl super::Operation for Request {
         app!()
             .map_err(internal_error)?
             .switchboard
-            .with_write_lock(|mut switchboard| {
+            .with_write_lock(|switchboard| {
                 // The stream still may be ongoing and we must stop it gracefully.
                 if let Some(publisher) = switchboard.publisher_of(self.id) {
                     warn!(
@@ -70,32 +77,53 @@ impl super::Operation for Request {
                     }
                 }
 
-                Ok(())
-            })
-            .map_err(internal_error)?;
+                // Readers waiting for a lost publisher won't get it back after the upload.
+                for reader in switchboard.remove_paused_stream(self.id) {
+                    switchboard.disconnect(reader)?;
+                }
+
+                Ok::<_, SwitchboardError>(())
+            })?;
         let recorder = app!()
             .map_err(internal_error)?
             .recorders_creator
             .new_handle(self.id);
         recorder.wait_stop().await.map_err(internal_error)?;
+        recorder.wait_segment_uploads().await;
 
         recorder
             .check_existence()
             .map_err(|err| error(StatusCode::NOT_FOUND, err))?;
 
         let _guard = MUTEX.lock().await;
+        let decrypted_segments = recorder.decrypt_segments().await.map_err(internal_error)?;
+        let upload_result = upload_record(self, &recorder.get_records_dir()).await;
+
+        // Plaintext segments are only needed for the upload.
+        for path in decrypted_segments {
+            if let Err(err) = fs::remove_file(&path) {
+                err!(
+                    "Failed to remove decrypted segment {}: {:?}", path.to_string_lossy(), err;
+                    {"rtc_id": self.id}
+                );
+            }
+        }
 
-        match upload_record(self).await.map_err(internal_error)? {
+        match upload_result.map_err(internal_error)? {
             UploadStatus::AlreadyRunning => {
                 Ok(serde_json::json!({"id": self.id, "state": "already_running"}).into())
             }
             UploadStatus::Done => {
                 let dumps = get_dump_uris(&recorder).map_err(internal_error)?;
+                let manifest = get_manifest(&recorder).map_err(internal_error)?;
+                let events = get_events(&recorder).map_err(internal_error)?;
                 recorder.delete_record().map_err(internal_error)?;
 
                 Ok(Response {
                     id: self.id,
                     mjr_dumps_uris: dumps,
+                    manifest,
+                    events,
                 }
                 .into())
             }
@@ -135,17 +163,15 @@ pub enum UploadStatus {
 
 const LOCKFILE_EARLY_EXIT_STATUS: i32 = 251;
 
-async fn upload_record(request: &Request) -> Result<UploadStatus> {
+async fn upload_record(request: &Request, records_dir: &Path) -> Result<UploadStatus> {
     info!("Preparing & uploading record"; {"rtc_id": request.id});
 
-    let mut script_path = std::env::current_exe()
-        .context("Failed to get current executable path")?
+    let recordings_dir = records_dir
         .parent()
-        .ok_or_else(|| format_err!("Missing current executable dir"))?
-        .to_path_buf();
+        .ok_or_else(|| format_err!("Records dir has no parent"))?;
 
-    script_path.push("upload_record.sh");
-    let mut command = Command::new(&script_path);
+    let mut command = Command::new(upload_script_path()?);
+    command.env("RECORDINGS_DIR", recordings_dir);
     let stream_id = request.id.to_string();
 
     command.args([&stream_id, &request.backend, &request.bucket]);
@@ -190,3 +216,30 @@ fn get_dump_uris(recorder: &RecorderHandle) -> Result<Vec<String>> {
         .lines()
         .collect::<Result<Vec<_>, _>>()?)
 }
+
+fn get_manifest(recorder: &RecorderHandle) -> Result<Option<JsonValue>> {
+    let mut path = recorder.get_records_dir();
+    path.push(MANIFEST_FILENAME);
+
+    match fs::read(path) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn get_events(recorder: &RecorderHandle) -> Result<Vec<JsonValue>> {
+    let mut path = recorder.get_records_dir();
+    path.push(EVENTS_FILENAME);
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
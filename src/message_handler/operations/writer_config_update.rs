@@ -5,8 +5,9 @@ use svc_error::Error as SvcError;
 
 use crate::{
     message_handler::generic::MethodKind,
+    recorder::StreamEvent,
     send_fir,
-    switchboard::{StreamId, WriterConfig},
+    switchboard::{StreamId, SwitchboardError, WriterConfig},
 };
 
 #[derive(Clone, Debug, Deserialize)]
@@ -45,32 +46,38 @@ impl super::Operation for Request {
         }
 
         // Update writer config for the stream.
-        app.switchboard
-            .with_write_lock(|mut switchboard| {
-                for config_item in &self.configs {
-                    let mut writer_config = WriterConfig::new();
-                    writer_config.set_send_video(config_item.send_video);
-                    writer_config.set_send_audio(config_item.send_audio);
-
-                    if let Some(video_remb) = config_item.video_remb {
-                        writer_config.set_video_remb(video_remb);
-                    }
-                    let prev_config =
-                        switchboard.set_writer_config(config_item.stream_id, writer_config);
-                    if let (Some(prev_config), Some(session_id)) =
-                        (prev_config, switchboard.publisher_of(config_item.stream_id))
+        app.switchboard.with_write_lock(|switchboard| {
+            for config_item in &self.configs {
+                let mut writer_config = WriterConfig::new();
+                writer_config.set_send_video(config_item.send_video);
+                writer_config.set_send_audio(config_item.send_audio);
+
+                if let Some(video_remb) = config_item.video_remb {
+                    writer_config.set_video_remb(video_remb);
+                }
+
+                let event = StreamEvent::WriterConfigUpdate {
+                    send_video: writer_config.send_video(),
+                    send_audio: writer_config.send_audio(),
+                    video_remb: writer_config.video_remb(),
+                };
+
+                switchboard.record_event(config_item.stream_id, event);
+                let prev_config =
+                    switchboard.set_writer_config(config_item.stream_id, writer_config);
+                if let (Some(prev_config), Some(session_id)) =
+                    (prev_config, switchboard.publisher_of(config_item.stream_id))
+                {
+                    if (config_item.send_audio && !prev_config.send_audio())
+                        || (config_item.send_video && !prev_config.send_video())
                     {
-                        if (config_item.send_audio && !prev_config.send_audio())
-                            || (config_item.send_video && !prev_config.send_video())
-                        {
-                            send_fir(session_id, &switchboard);
-                        }
+                        send_fir(session_id, &app.switchboard.relay_table());
                     }
                 }
+            }
 
-                Ok(())
-            })
-            .map_err(internal_error)?;
+            Ok::<_, SwitchboardError>(())
+        })?;
 
         Ok(Response {}.into())
     }
@@ -1,6 +1,6 @@
 use std::time::{Duration, Instant};
 
-use crate::{message_handler::MethodKind, switchboard::Switchboard};
+use crate::{message_handler::MethodKind, recorder::ReorderStats, switchboard::Switchboard};
 use http::StatusCode;
 use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
 use prometheus_static_metric::make_static_metric;
@@ -27,6 +27,8 @@ make_static_metric! {
         "method" => {
             agent_leave,
             reader_config_update,
+            recording_delete,
+            recording_list,
             stream_create,
             stream_read,
             stream_upload,
@@ -36,6 +38,15 @@ make_static_metric! {
     }
 }
 
+make_static_metric! {
+    pub struct FanOutDuration: Histogram {
+        "mode" => {
+            inline,
+            parallel,
+        },
+    }
+}
+
 make_static_metric! {
     pub struct SwitchboardStats: IntGauge {
         "field" => {
@@ -52,11 +63,20 @@ make_static_metric! {
 }
 
 make_static_metric! {
-    pub struct RecorderStats: IntGauge {
+    pub struct VacuumedSessions: IntCounter {
+        "role" => {
+            publisher,
+            reader,
+        },
+    }
+}
+
+make_static_metric! {
+    pub struct RecorderReorderStats: IntCounter {
         "field" => {
-            recorders,
-            waiters,
-            queue
+            lost,
+            duplicates,
+            late,
         },
     }
 }
@@ -66,7 +86,13 @@ pub struct Metrics {
     request_stats: RequestStats,
     response_stats: ResponseStats,
     switchboard_stats: SwitchboardStats,
-    recorder_stats: RecorderStats,
+    recorder_stats: IntGaugeVec,
+    recorder_dropped_packets: IntCounterVec,
+    recorder_reorder_stats: RecorderReorderStats,
+    recordings_disk_stats: IntGaugeVec,
+    fan_out_duration: FanOutDuration,
+    fan_out_dropped_packets: IntCounterVec,
+    vacuumed_sessions: VacuumedSessions,
 }
 
 impl std::fmt::Debug for Metrics {
@@ -91,20 +117,80 @@ impl Metrics {
             Opts::new("switchboard_stats", "Switchboard stats"),
             &["field"],
         )?;
-        let recorder_stats =
-            IntGaugeVec::new(Opts::new("recorder_stats", "Recorder stats"), &["field"])?;
+        let recorder_stats = IntGaugeVec::new(
+            Opts::new("recorder_stats", "Recorder stats"),
+            &["field", "shard"],
+        )?;
+        let recorder_dropped_packets = IntCounterVec::new(
+            Opts::new(
+                "recorder_dropped_packets",
+                "Packets dropped due to recorder queue overflow",
+            ),
+            &["shard", "media"],
+        )?;
+        let recorder_reorder_stats = IntCounterVec::new(
+            Opts::new("recorder_reorder_stats", "Recorder reorder buffer stats"),
+            &["field"],
+        )?;
+
+        let recordings_disk_stats = IntGaugeVec::new(
+            Opts::new(
+                "recordings_disk_stats",
+                "Recordings directory disk usage in bytes",
+            ),
+            &["field", "volume"],
+        )?;
+
+        let fan_out_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "fan_out_duration",
+                "Time to relay an RTP packet to all readers of a stream",
+            )
+            .buckets(vec![
+                0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025,
+            ]),
+            &["mode"],
+        )?;
+
+        let fan_out_dropped_packets = IntCounterVec::new(
+            Opts::new(
+                "fan_out_dropped_packets",
+                "Packets dropped due to fan-out worker queue overflow",
+            ),
+            &["worker", "media"],
+        )?;
+
+        let vacuumed_sessions = IntCounterVec::new(
+            Opts::new(
+                "vacuumed_sessions",
+                "Sessions disconnected for not sending packets",
+            ),
+            &["role"],
+        )?;
 
         registry.register(Box::new(request_duration.clone()))?;
         registry.register(Box::new(request_stats.clone()))?;
         registry.register(Box::new(switchboard_stats.clone()))?;
         registry.register(Box::new(recorder_stats.clone()))?;
         registry.register(Box::new(response_stats.clone()))?;
+        registry.register(Box::new(recorder_dropped_packets.clone()))?;
+        registry.register(Box::new(recorder_reorder_stats.clone()))?;
+        registry.register(Box::new(recordings_disk_stats.clone()))?;
+        registry.register(Box::new(fan_out_duration.clone()))?;
+        registry.register(Box::new(fan_out_dropped_packets.clone()))?;
+        registry.register(Box::new(vacuumed_sessions.clone()))?;
         Ok(Self {
             request_duration: RequestDuration::from(&request_duration),
             request_stats: RequestStats::from(&request_stats),
             switchboard_stats: SwitchboardStats::from(&switchboard_stats),
-            recorder_stats: RecorderStats::from(&recorder_stats),
+            recorder_stats,
             response_stats: ResponseStats::from(&response_stats),
+            recorder_dropped_packets,
+            recorder_reorder_stats: RecorderReorderStats::from(&recorder_reorder_stats),
+            recordings_disk_stats,
+            fan_out_duration: FanOutDuration::from(&fan_out_duration),
+            fan_out_dropped_packets,
+            vacuumed_sessions: VacuumedSessions::from(&vacuumed_sessions),
         })
     }
 
@@ -167,6 +253,8 @@ impl Metrics {
                 MethodKind::ReaderConfigUpdate => {
                     request_duration.reader_config_update.observe(elapsed)
                 }
+                MethodKind::RecordingDelete => request_duration.recording_delete.observe(elapsed),
+                MethodKind::RecordingList => request_duration.recording_list.observe(elapsed),
 
                 MethodKind::StreamCreate => request_duration.stream_create.observe(elapsed),
                 MethodKind::StreamRead => request_duration.stream_read.observe(elapsed),
@@ -179,14 +267,95 @@ impl Metrics {
         }
     }
 
-    pub fn observe_recorder(recorders_count: usize, queue_size: usize, waiters_size: usize) {
+    pub fn observe_recorder(
+        shard: usize,
+        recorders_count: usize,
+        queue_size: usize,
+        waiters_size: usize,
+    ) {
         if let Ok(app) = app!() {
-            app.metrics
-                .recorder_stats
-                .recorders
+            let shard = shard.to_string();
+            let recorder_stats = &app.metrics.recorder_stats;
+
+            recorder_stats
+                .with_label_values(&["recorders", &shard])
                 .set(recorders_count as i64);
-            app.metrics.recorder_stats.queue.set(queue_size as i64);
-            app.metrics.recorder_stats.waiters.set(waiters_size as i64);
+            recorder_stats
+                .with_label_values(&["queue", &shard])
+                .set(queue_size as i64);
+            recorder_stats
+                .with_label_values(&["waiters", &shard])
+                .set(waiters_size as i64);
+        }
+    }
+
+    pub fn observe_recorder_dropped_packet(shard: usize, is_video: bool) {
+        if let Ok(app) = app!() {
+            let media = if is_video { "video" } else { "audio" };
+
+            app.metrics
+                .recorder_dropped_packets
+                .with_label_values(&[&shard.to_string(), media])
+                .inc();
+        }
+    }
+
+    pub fn observe_recorder_reorder(stats: ReorderStats) {
+        if let Ok(app) = app!() {
+            let reorder_stats = &app.metrics.recorder_reorder_stats;
+            reorder_stats.lost.inc_by(stats.lost);
+            reorder_stats.duplicates.inc_by(stats.duplicates);
+            reorder_stats.late.inc_by(stats.late);
+        }
+    }
+
+    pub fn observe_recordings_disk(volume: &str, free_space: u64, retained_bytes: u64) {
+        if let Ok(app) = app!() {
+            let disk_stats = &app.metrics.recordings_disk_stats;
+
+            disk_stats
+                .with_label_values(&["free_space", volume])
+                .set(free_space as i64);
+            disk_stats
+                .with_label_values(&["retained_bytes", volume])
+                .set(retained_bytes as i64);
+        }
+    }
+
+    /// Observes the time to relay a packet to the readers of a stream.
+    /// In parallel mode it's observed by each worker including the time in its queue.
+    pub fn observe_fan_out(start_time: Instant, is_parallel: bool) {
+        let elapsed = Self::duration_to_seconds(start_time.elapsed());
+
+        if let Ok(app) = app!() {
+            let fan_out_duration = &app.metrics.fan_out_duration;
+
+            match is_parallel {
+                true => fan_out_duration.parallel.observe(elapsed),
+                false => fan_out_duration.inline.observe(elapsed),
+            }
+        }
+    }
+
+    pub fn observe_fan_out_dropped_packet(worker: usize, is_video: bool) {
+        if let Ok(app) = app!() {
+            let media = if is_video { "video" } else { "audio" };
+
+            app.metrics
+                .fan_out_dropped_packets
+                .with_label_values(&[&worker.to_string(), media])
+                .inc();
+        }
+    }
+
+    pub fn observe_vacuumed_session(is_reader: bool) {
+        if let Ok(app) = app!() {
+            let vacuumed_sessions = &app.metrics.vacuumed_sessions;
+
+            match is_reader {
+                true => vacuumed_sessions.reader.inc(),
+                false => vacuumed_sessions.publisher.inc(),
+            }
         }
     }
 
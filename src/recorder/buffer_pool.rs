@@ -0,0 +1,115 @@
+//! Reusable buffers for RTP packets passed from Janus callbacks to recorder threads.
+//!
+//! The module only depends on `std` so it's included into the benchmark as is.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+/// Capacity of a pooled buffer. Larger packets get a dedicated allocation.
+pub const BUFFER_SIZE: usize = 1500;
+
+/// A free list of fixed-size buffers shared between the producers and the recorder threads.
+#[derive(Debug)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<i8>>>,
+    max_free: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool keeping at most `max_free` unused buffers.
+    pub fn new(max_free: usize) -> Arc<Self> {
+        Arc::new(Self {
+            free: Mutex::new(Vec::new()),
+            max_free,
+        })
+    }
+
+    /// Copies the packet into a buffer taken from the pool.
+    pub fn copy_from(self: &Arc<Self>, packet: &[i8]) -> PooledBuffer {
+        let buf = if packet.len() <= BUFFER_SIZE {
+            self.free
+                .lock()
+                .expect("Buffer pool lock poisoned")
+                .pop()
+                .unwrap_or_else(|| Vec::with_capacity(BUFFER_SIZE))
+        } else {
+            Vec::with_capacity(packet.len())
+        };
+
+        let mut buf = PooledBuffer {
+            buf,
+            pool: self.clone(),
+        };
+
+        buf.buf.extend_from_slice(packet);
+        buf
+    }
+
+    #[cfg(test)]
+    fn free_count(&self) -> usize {
+        self.free.lock().expect("Buffer pool lock poisoned").len()
+    }
+
+    fn release(&self, mut buf: Vec<i8>) {
+        if buf.capacity() != BUFFER_SIZE {
+            return;
+        }
+
+        let mut free = self.free.lock().expect("Buffer pool lock poisoned");
+
+        if free.len() < self.max_free {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+}
+
+/// Packet copy which returns its buffer to the pool on drop.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<i8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [i8];
+
+    fn deref(&self) -> &[i8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferPool, BUFFER_SIZE};
+
+    #[test]
+    fn reuse_buffers() {
+        let pool = BufferPool::new(1);
+        let first = pool.copy_from(&[1, 2, 3]);
+        let second = pool.copy_from(&[4, 5]);
+        assert_eq!(&*first, &[1, 2, 3]);
+        assert_eq!(&*second, &[4, 5]);
+
+        drop(first);
+        drop(second);
+        assert_eq!(pool.free_count(), 1);
+
+        let third = pool.copy_from(&[6]);
+        assert_eq!(&*third, &[6]);
+        assert_eq!(pool.free_count(), 0);
+
+        // Oversized packets don't go to the pool.
+        drop(third);
+        drop(pool.copy_from(&vec![0; BUFFER_SIZE + 1]));
+        assert_eq!(pool.free_count(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+//! Envelope encryption of closed record segments.
+//!
+//! Each records dir has its own random data key stored in `data_key.json` wrapped
+//! with the configured master key. Segments are encrypted with the data key once closed
+//! to `<filename>.enc` files and the plaintext ones get removed.
+//!
+//! The encrypted file is `JCENC001` magic, a 7-byte random nonce prefix and then the plaintext
+//! split into 64 KiB chunks each sealed with AES-256-GCM. A chunk nonce is the prefix,
+//! 32-bit big endian chunk number and the last chunk flag so chunks can't be reordered
+//! and the file can't be truncated unnoticed.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, NewAead},
+    Aes256Gcm,
+};
+use anyhow::{Context, Result};
+use rand::RngCore;
+
+pub const DATA_KEY_FILENAME: &str = "data_key.json";
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+
+const MAGIC: &[u8] = b"JCENC001";
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const NONCE_PREFIX_SIZE: usize = 7;
+
+#[derive(Clone, Deserialize)]
+pub struct EncryptionConfig {
+    /// Base64 encoded 256-bit key wrapping data keys of the records.
+    master_key: String,
+}
+
+impl fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("master_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl EncryptionConfig {
+    pub fn check(&self) -> Result<()> {
+        self.master_cipher().map(|_| ())
+    }
+
+    fn master_cipher(&self) -> Result<Aes256Gcm> {
+        let key = base64::decode(&self.master_key).context("Master key is not valid base64")?;
+
+        if key.len() != KEY_SIZE {
+            bail!("Master key must be {} bytes long", KEY_SIZE);
+        }
+
+        Ok(Aes256Gcm::new(GenericArray::from_slice(&key)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WrappedKey {
+    wrapped_key: String,
+    nonce: String,
+}
+
+/// Data key of a records dir.
+pub struct DataKey {
+    cipher: Aes256Gcm,
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DataKey")
+    }
+}
+
+impl DataKey {
+    /// Unwraps the data key of the records dir or generates a new one if it's missing.
+    pub fn load_or_create(dir: &str, config: &EncryptionConfig) -> Result<Self> {
+        let master_cipher = config.master_cipher()?;
+        let path = Path::new(dir).join(DATA_KEY_FILENAME);
+
+        match fs::read(&path) {
+            Ok(content) => {
+                let wrapped: WrappedKey =
+                    serde_json::from_slice(&content).context("Failed to parse data key")?;
+
+                let nonce = base64::decode(&wrapped.nonce).context("Invalid data key nonce")?;
+                let wrapped_key =
+                    base64::decode(&wrapped.wrapped_key).context("Invalid data key")?;
+
+                if nonce.len() != NONCE_SIZE {
+                    bail!("Invalid data key nonce length");
+                }
+
+                let key = master_cipher
+                    .decrypt(GenericArray::from_slice(&nonce), wrapped_key.as_ref())
+                    .map_err(|_| anyhow!("Failed to unwrap data key; wrong master key?"))?;
+
+                if key.len() != KEY_SIZE {
+                    bail!("Invalid data key length");
+                }
+
+                Ok(Self::new(&key))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let mut key = [0; KEY_SIZE];
+                let mut nonce = [0; NONCE_SIZE];
+                rand::thread_rng().fill_bytes(&mut key);
+                rand::thread_rng().fill_bytes(&mut nonce);
+
+                let wrapped_key = master_cipher
+                    .encrypt(GenericArray::from_slice(&nonce), key.as_ref())
+                    .map_err(|_| anyhow!("Failed to wrap data key"))?;
+
+                let wrapped = WrappedKey {
+                    wrapped_key: base64::encode(wrapped_key),
+                    nonce: base64::encode(nonce),
+                };
+
+                let tmp_path = path.with_extension("json.tmp");
+                fs::write(&tmp_path, serde_json::to_vec_pretty(&wrapped)?)
+                    .context("Failed to write data key")?;
+                fs::rename(&tmp_path, &path).context("Failed to save data key")?;
+                Ok(Self::new(&key))
+            }
+            Err(err) => Err(err).context("Failed to read data key"),
+        }
+    }
+
+    fn new(key: &[u8]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(GenericArray::from_slice(key)),
+        }
+    }
+
+    /// Encrypts the file to `<path>.enc` and removes the plaintext one.
+    pub fn encrypt_file(&self, path: &Path) -> Result<PathBuf> {
+        let encrypted_path = encrypted_path(path);
+        let tmp_path = encrypted_path.with_extension("enc.tmp");
+
+        // Don't leave a partially written file behind on failure.
+        if let Err(err) = self.write_encrypted(path, &tmp_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+
+        fs::rename(&tmp_path, &encrypted_path).context("Failed to save encrypted file")?;
+        fs::remove_file(path).context("Failed to remove plaintext file")?;
+        Ok(encrypted_path)
+    }
+
+    fn write_encrypted(&self, path: &Path, tmp_path: &Path) -> Result<()> {
+        let mut reader = BufReader::new(File::open(path).context("Failed to open file")?);
+        let file = File::create(tmp_path).context("Failed to create encrypted file")?;
+        let mut writer = BufWriter::new(&file);
+
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce_prefix);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&nonce_prefix)?;
+
+        self.transform_chunks(
+            &mut reader,
+            &mut writer,
+            &nonce_prefix,
+            CHUNK_SIZE,
+            |nonce, chunk| {
+                self.cipher
+                    .encrypt(nonce, chunk)
+                    .map_err(|_| anyhow!("Failed to encrypt chunk"))
+            },
+        )?;
+
+        writer.flush()?;
+        drop(writer);
+        file.sync_all().context("Failed to sync encrypted file")
+    }
+
+    /// Decrypts `<path>.enc` file to `path` keeping the encrypted one.
+    pub fn decrypt_file(&self, path: &Path) -> Result<()> {
+        let encrypted_path = encrypted_path(path);
+        let file = File::open(&encrypted_path).context("Failed to open encrypted file")?;
+        let mut reader = BufReader::new(file);
+        let mut header = [0; MAGIC.len() + NONCE_PREFIX_SIZE];
+
+        reader
+            .read_exact(&mut header)
+            .context("Failed to read encrypted file header")?;
+
+        if &header[..MAGIC.len()] != MAGIC {
+            bail!("Unknown encrypted file format");
+        }
+
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&header[MAGIC.len()..]);
+        let tmp_path = encrypted_path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path).context("Failed to create file")?);
+
+        let result = self.transform_chunks(
+            &mut reader,
+            &mut writer,
+            &nonce_prefix,
+            CHUNK_SIZE + TAG_SIZE,
+            |nonce, chunk| {
+                self.cipher
+                    .decrypt(nonce, chunk)
+                    .map_err(|_| anyhow!("Encrypted file is corrupted or truncated"))
+            },
+        );
+
+        if let Err(err) = result.and_then(|()| Ok(writer.flush()?)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+
+        fs::rename(&tmp_path, path).context("Failed to save decrypted file")?;
+        Ok(())
+    }
+
+    /// Reads the input in chunks of `chunk_size`, transforms and writes them out.
+    /// The next chunk is read ahead to tell whether the current one is the last.
+    fn transform_chunks<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        nonce_prefix: &[u8; NONCE_PREFIX_SIZE],
+        chunk_size: usize,
+        transform: impl Fn(&GenericArray<u8, <Aes256Gcm as Aead>::NonceSize>, &[u8]) -> Result<Vec<u8>>,
+    ) -> Result<()> {
+        let mut chunk = read_chunk(reader, chunk_size)?;
+        let mut counter: u32 = 0;
+
+        loop {
+            let next = if chunk.len() == chunk_size {
+                read_chunk(reader, chunk_size)?
+            } else {
+                Vec::new()
+            };
+
+            let is_last = next.is_empty();
+            let nonce = chunk_nonce(nonce_prefix, counter, is_last);
+            writer.write_all(&transform(GenericArray::from_slice(&nonce), &chunk)?)?;
+
+            if is_last {
+                return Ok(());
+            }
+
+            chunk = next;
+            counter = counter.checked_add(1).context("File is too large")?;
+        }
+    }
+}
+
+/// Returns the path of the encrypted file for the plaintext one.
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut encrypted_path = path.as_os_str().to_owned();
+    encrypted_path.push(".");
+    encrypted_path.push(ENCRYPTED_EXTENSION);
+    encrypted_path.into()
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_SIZE], counter: u32, is_last: bool) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..NONCE_SIZE - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_SIZE - 1] = is_last as u8;
+    nonce
+}
+
+/// Reads up to `size` bytes stopping early only at the end of the input.
+fn read_chunk<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{encrypted_path, DataKey, EncryptionConfig, CHUNK_SIZE};
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir(&dir).expect("Failed to create dir");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let config = EncryptionConfig {
+            master_key: base64::encode([7; 32]),
+        };
+
+        let key = DataKey::load_or_create(&dir_str, &config).expect("Failed to create key");
+
+        for len in &[0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 5] {
+            let path = dir.join(format!("{}.mjr", len));
+            let content = (0..*len).map(|i| i as u8).collect::<Vec<_>>();
+            fs::write(&path, &content).expect("Failed to write file");
+            key.encrypt_file(&path).expect("Failed to encrypt");
+            assert!(!path.exists());
+
+            // The key must be the same after reload.
+            let key = DataKey::load_or_create(&dir_str, &config).expect("Failed to load key");
+            key.decrypt_file(&path).expect("Failed to decrypt");
+            assert_eq!(fs::read(&path).expect("Failed to read file"), content);
+        }
+
+        // Truncation at a chunk boundary is detected.
+        let path = dir.join(format!("{}.mjr", CHUNK_SIZE * 2 + 5));
+        let enc_path = encrypted_path(&path);
+        let encrypted = fs::read(&enc_path).expect("Failed to read file");
+        fs::write(&enc_path, &encrypted[..encrypted.len() - 21]).expect("Failed to write file");
+        let result = key.decrypt_file(&path);
+        fs::remove_dir_all(&dir).expect("Failed to remove dir");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+
+use crate::switchboard::AgentId;
+
+pub const EVENTS_FILENAME: &str = "events.jsonl";
+
+/// Something that happened to the stream while it was being recorded.
+///
+/// Events are appended to `events.jsonl` in the records dir one JSON object per line
+/// so editors could tell e.g. a mute from a broken mic.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    WriterConfigUpdate {
+        send_video: bool,
+        send_audio: bool,
+        video_remb: u32,
+    },
+    ReaderConfigUpdate {
+        reader_id: AgentId,
+        receive_video: bool,
+        receive_audio: bool,
+    },
+    /// Another agent has published to the stream replacing the current publisher.
+    PublisherTakeover {
+        previous_agent_id: Option<AgentId>,
+        agent_id: AgentId,
+    },
+    /// No packets of the media were received for `duration` milliseconds.
+    Pause { media: Media, duration: i64 },
+    /// The recording has been stopped because the recorder couldn't keep up with the stream.
+    QueueOverflow,
+    SpeakingChange {
+        agent_id: AgentId,
+        is_speaking: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Media {
+    Audio,
+    Video,
+}
+
+impl Media {
+    pub fn new(is_video: bool) -> Self {
+        if is_video {
+            Self::Video
+        } else {
+            Self::Audio
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventLine<'a> {
+    /// Unix timestamp in milliseconds.
+    time: i64,
+    #[serde(flatten)]
+    event: &'a StreamEvent,
+}
+
+pub fn append_event(dir: &str, time: i64, event: &StreamEvent) -> Result<()> {
+    let mut line = serde_json::to_vec(&EventLine { time, event })?;
+    line.push(b'\n');
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(Path::new(dir).join(EVENTS_FILENAME))
+        .and_then(|mut file| file.write_all(&line))
+        .context("Failed to write event")
+}
@@ -0,0 +1,209 @@
+use std::{ffi::CString, fs, io, mem::MaybeUninit, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use fnv::FnvHashMap;
+
+use super::records::{list_records, RecordInfo, RecordState, UploadState};
+use super::Config;
+use crate::switchboard::StreamId;
+
+/// Returns the number of bytes available to unprivileged users on the filesystem of the path.
+pub fn free_space(path: &str) -> Result<u64> {
+    let c_path = CString::new(path).context("Invalid path")?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `c_path` is a valid C string and `stat` is only read if the call succeeds.
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to get filesystem stats");
+    }
+
+    let stat = unsafe { stat.assume_init() };
+
+    // Field types differ across platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Deletes records exceeding `max_age` and `max_total_size` and returns the size
+/// of the rest per recordings directory.
+///
+/// Records being recorded or uploaded are never deleted though they count in the total size.
+/// Records which haven't been uploaded completely are deleted only when they exceed `max_age`.
+pub fn clean_records(
+    config: &Config,
+    is_recording: impl Fn(StreamId) -> bool,
+    is_uploading: impl Fn(StreamId) -> bool,
+) -> Result<FnvHashMap<String, u64>> {
+    let volumes = config.volumes();
+    let records = list_records(&volumes, is_recording, is_uploading)?;
+    let expired = select_expired(&records, config.max_age, config.max_total_size);
+
+    let mut retained_bytes = volumes
+        .into_iter()
+        .map(|volume| (volume, 0))
+        .collect::<FnvHashMap<_, _>>();
+
+    for record in records {
+        if expired.contains(&record.id) {
+            let path = Path::new(&record.volume).join(record.id.to_string());
+
+            match fs::remove_dir_all(&path) {
+                Ok(()) => {
+                    info!(
+                        "Deleted record by retention policy: age = {}s, size = {}", record.age, record.size;
+                        {"rtc_id": record.id}
+                    );
+
+                    continue;
+                }
+                Err(err) => err!("Failed to delete record: {:?}", err; {"rtc_id": record.id}),
+            }
+        }
+
+        *retained_bytes.entry(record.volume).or_insert(0) += record.size;
+    }
+
+    Ok(retained_bytes)
+}
+
+/// Picks records to delete: the ones older than `max_age` and then the oldest ones
+/// until the total size fits `max_total_size` skipping the ones not uploaded yet.
+/// Records are expected to be sorted by age.
+fn select_expired(
+    records: &[RecordInfo],
+    max_age: Option<Duration>,
+    max_total_size: Option<u64>,
+) -> Vec<StreamId> {
+    let mut total_size = records.iter().map(|record| record.size).sum::<u64>();
+    let mut expired = Vec::new();
+
+    for record in records {
+        if record.state == RecordState::Recording || record.upload_state == UploadState::Uploading {
+            continue;
+        }
+
+        let is_too_old = max_age.map_or(false, |max_age| record.age as u64 > max_age.as_secs());
+        let is_too_large = max_total_size.map_or(false, |max_size| total_size > max_size);
+
+        // Records not uploaded completely would be lost so they're only deleted when expired by age.
+        let is_too_large = is_too_large && record.upload_state == UploadState::Uploaded;
+
+        if is_too_old || is_too_large {
+            expired.push(record.id);
+            total_size -= record.size;
+        }
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use uuid::Uuid;
+
+    use super::select_expired;
+    use crate::recorder::{
+        manifest::{Manifest, SegmentEntry},
+        records::{list_records, RecordInfo, RecordState, UploadState},
+    };
+
+    fn record(age: i64, size: u64, state: RecordState) -> RecordInfo {
+        RecordInfo {
+            id: Uuid::new_v4(),
+            volume: String::new(),
+            state,
+            upload_state: UploadState::Uploaded,
+            segments_count: 1,
+            size,
+            created_at: 0,
+            age,
+        }
+    }
+
+    #[test]
+    fn select_by_age_and_size() {
+        let records = vec![
+            record(300, 10, RecordState::Recording),
+            record(200, 10, RecordState::Completed),
+            record(100, 10, RecordState::Completed),
+            record(50, 10, RecordState::Completed),
+            record(10, 10, RecordState::Completed),
+        ];
+
+        let expired = select_expired(&records, Some(Duration::from_secs(150)), None);
+        assert_eq!(expired, vec![records[1].id]);
+
+        let expired = select_expired(&records, None, Some(25));
+        assert_eq!(expired, vec![records[1].id, records[2].id, records[3].id]);
+
+        let expired = select_expired(&records, Some(Duration::from_secs(75)), Some(40));
+        assert_eq!(expired, vec![records[1].id, records[2].id]);
+        assert!(select_expired(&records, None, None).is_empty());
+    }
+
+    #[test]
+    fn keep_not_uploaded_until_max_age() {
+        let mut records = vec![
+            record(200, 10, RecordState::Completed),
+            record(100, 10, RecordState::Completed),
+            record(50, 10, RecordState::Completed),
+        ];
+
+        records[0].upload_state = UploadState::NotUploaded;
+        records[1].upload_state = UploadState::NotUploaded;
+
+        let expired = select_expired(&records, None, Some(15));
+        assert_eq!(expired, vec![records[2].id]);
+
+        let expired = select_expired(&records, Some(Duration::from_secs(150)), Some(15));
+        assert_eq!(expired, vec![records[0].id, records[2].id]);
+    }
+
+    #[test]
+    fn evict_only_completely_uploaded_by_size() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let volume = root.to_string_lossy().into_owned();
+
+        // Segments shipped by live upload without the tail and a failed `stream.upload`.
+        let dirs = [
+            (100, vec!["1_0.mjr", "uploaded.txt"]),
+            (200, vec!["1_0.mjr", "dumps.txt"]),
+            (300, vec!["1_0.mjr", "dumps.txt", "upload_done"]),
+        ];
+
+        for (start_time, files) in &dirs {
+            let dir = root.join(Uuid::new_v4().to_string());
+            fs::create_dir_all(&dir).expect("Failed to create dir");
+
+            let entry = SegmentEntry::new(0, String::from("1_0.mjr"), "opus", *start_time);
+            let mut manifest = Manifest::default();
+            manifest.upsert(&entry);
+            manifest
+                .save(&dir.to_string_lossy())
+                .expect("Failed to save manifest");
+
+            for filename in files {
+                fs::write(dir.join(filename), b"1234").expect("Failed to write file");
+            }
+        }
+
+        let records = list_records(&[volume], |_| false, |_| false);
+        fs::remove_dir_all(&root).expect("Failed to remove dir");
+        let records = records.expect("Failed to list records");
+
+        let upload_states = records.iter().map(|r| r.upload_state).collect::<Vec<_>>();
+
+        let expected_upload_states = [
+            UploadState::PartiallyUploaded,
+            UploadState::PartiallyUploaded,
+            UploadState::Uploaded,
+        ];
+
+        assert_eq!(upload_states, expected_upload_states);
+
+        let expired = select_expired(&records, None, Some(1));
+        assert_eq!(expired, vec![records[2].id]);
+    }
+}
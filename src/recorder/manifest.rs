@@ -0,0 +1,353 @@
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+};
+
+use anyhow::{format_err, Context, Result};
+use fnv::FnvHashMap;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use super::reorder::ReorderStats;
+use crate::rtp::{RtpHeader, SenderReport};
+
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Maximum number of sequence gaps listed per segment. The rest are only counted.
+const MAX_GAPS: usize = 1000;
+
+/// Describes the layout of a stream record for downstream processing.
+///
+/// It's stored as `manifest.json` in the records dir and gets rewritten
+/// each time a segment is opened or closed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub segments: Vec<SegmentEntry>,
+}
+
+impl Manifest {
+    pub fn load(dir: &str) -> Result<Self> {
+        let path = Path::new(dir).join(MANIFEST_FILENAME);
+
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).context("Failed to parse manifest"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).context("Failed to read manifest"),
+        }
+    }
+
+    pub fn save(&self, dir: &str) -> Result<()> {
+        let path = Path::new(dir).join(MANIFEST_FILENAME);
+        let tmp_path = path.with_extension("json.tmp");
+
+        // Write to a temporary file first to never leave a partially written manifest.
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)
+            .context("Failed to write manifest")?;
+
+        fs::rename(&tmp_path, &path).context("Failed to replace manifest")
+    }
+
+    pub fn upsert(&mut self, entry: &SegmentEntry) {
+        match self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.filename == entry.filename)
+        {
+            Some(segment) => *segment = entry.clone(),
+            None => self.segments.push(entry.clone()),
+        }
+    }
+}
+
+/// Manifests being written by recorders and segment jobs by records dir.
+static SHARED_MANIFESTS: Lazy<Mutex<FnvHashMap<String, Weak<Mutex<Manifest>>>>> =
+    Lazy::new(Default::default);
+
+/// The manifest of a records dir shared between its recorder and background segment jobs.
+///
+/// There's a single instance per dir so the jobs of a previous recording of the stream
+/// can't overwrite the manifest with their stale copy.
+#[derive(Debug, Clone)]
+pub struct SharedManifest {
+    dir: String,
+    manifest: Arc<Mutex<Manifest>>,
+}
+
+impl SharedManifest {
+    pub fn load(dir: &str) -> Result<Self> {
+        let mut shared_manifests = SHARED_MANIFESTS
+            .lock()
+            .map_err(|_| format_err!("Shared manifests lock poisoned"))?;
+
+        shared_manifests.retain(|_, manifest| manifest.strong_count() > 0);
+
+        let manifest = match shared_manifests.get(dir).and_then(Weak::upgrade) {
+            Some(manifest) => manifest,
+            None => {
+                let manifest = Arc::new(Mutex::new(Manifest::load(dir)?));
+                shared_manifests.insert(dir.to_owned(), Arc::downgrade(&manifest));
+                manifest
+            }
+        };
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            manifest,
+        })
+    }
+
+    /// Changes the manifest and saves it.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Manifest) -> R) -> Result<R> {
+        let mut manifest = self
+            .manifest
+            .lock()
+            .map_err(|_| format_err!("Manifest lock poisoned"))?;
+
+        let result = f(&mut manifest);
+        manifest.save(&self.dir)?;
+        Ok(result)
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&Manifest) -> R) -> Result<R> {
+        let manifest = self
+            .manifest
+            .lock()
+            .map_err(|_| format_err!("Manifest lock poisoned"))?;
+
+        Ok(f(&manifest))
+    }
+
+    /// Calculates the checksum of a closed segment file and saves it to the manifest.
+    pub fn set_checksum(&self, filename: &str) -> Result<()> {
+        let sha256 = checksum(&Path::new(&self.dir).join(filename))?;
+
+        self.update(|manifest| {
+            let segment = manifest
+                .segments
+                .iter_mut()
+                .find(|segment| segment.filename == filename);
+
+            if let Some(segment) = segment {
+                segment.sha256 = Some(sha256);
+            }
+        })
+    }
+}
+
+/// A single MJR file of a record segment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentEntry {
+    pub index: usize,
+    pub filename: String,
+    pub codec: String,
+    #[serde(default)]
+    pub state: SegmentState,
+    /// Unix timestamp in milliseconds.
+    pub start_time: i64,
+    /// Unix timestamp in milliseconds. Missing for the segment being recorded.
+    pub end_time: Option<i64>,
+    pub first_rtp_timestamp: Option<u32>,
+    pub last_rtp_timestamp: Option<u32>,
+    pub packets_count: u64,
+    pub lost_packets_count: u64,
+    pub gaps: Vec<SequenceGap>,
+    /// Packets dropped by the reorder buffer as received more than once.
+    #[serde(default)]
+    pub duplicate_packets_count: u64,
+    /// Packets dropped by the reorder buffer as arrived after their turn.
+    #[serde(default)]
+    pub late_packets_count: u64,
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    /// NTP ↔ RTP timestamps mappings from the publisher's RTCP sender reports
+    /// which allow to align tracks and streams in post-processing.
+    pub first_sender_report: Option<SenderReportEntry>,
+    pub last_sender_report: Option<SenderReportEntry>,
+    #[serde(skip)]
+    last_seq: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentState {
+    /// The segment is being written.
+    Recording,
+    /// The segment has been closed properly.
+    Completed,
+    /// The recording has been interrupted by a crash and the segment has been repaired on startup.
+    Recovered,
+    /// The recording has been interrupted by a crash and the segment has no frames to recover.
+    Interrupted,
+}
+
+impl Default for SegmentState {
+    fn default() -> Self {
+        Self::Completed
+    }
+}
+
+/// Packets missing right after the packet with `after_seq` sequence number.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub after_seq: u16,
+    pub missing: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SenderReportEntry {
+    pub ntp_timestamp: u64,
+    pub rtp_timestamp: u32,
+    /// NTP timestamp converted to Unix timestamp in milliseconds.
+    pub time: i64,
+}
+
+impl From<SenderReport> for SenderReportEntry {
+    fn from(report: SenderReport) -> Self {
+        Self {
+            ntp_timestamp: report.ntp_timestamp,
+            rtp_timestamp: report.rtp_timestamp,
+            time: report.unix_timestamp_millis(),
+        }
+    }
+}
+
+impl SegmentEntry {
+    pub fn new(index: usize, filename: String, codec: &str, start_time: i64) -> Self {
+        Self {
+            index,
+            filename,
+            codec: codec.to_owned(),
+            state: SegmentState::Recording,
+            start_time,
+            end_time: None,
+            first_rtp_timestamp: None,
+            last_rtp_timestamp: None,
+            packets_count: 0,
+            lost_packets_count: 0,
+            gaps: Vec::new(),
+            duplicate_packets_count: 0,
+            late_packets_count: 0,
+            size: None,
+            sha256: None,
+            first_sender_report: None,
+            last_sender_report: None,
+            last_seq: None,
+        }
+    }
+
+    pub fn observe_packet(&mut self, packet: &[u8]) {
+        self.packets_count += 1;
+
+        let header = match RtpHeader::parse(packet) {
+            Some(header) => header,
+            None => return,
+        };
+
+        if let Some(last_seq) = self.last_seq {
+            let delta = header.seq.wrapping_sub(last_seq);
+
+            // Deltas over a half of the sequence space mean a late or duplicated packet.
+            if delta == 0 || delta >= 0x8000 {
+                return;
+            }
+
+            if delta > 1 {
+                let missing = delta - 1;
+                self.lost_packets_count += missing as u64;
+
+                if self.gaps.len() < MAX_GAPS {
+                    self.gaps.push(SequenceGap {
+                        after_seq: last_seq,
+                        missing,
+                    });
+                }
+            }
+        }
+
+        self.first_rtp_timestamp.get_or_insert(header.timestamp);
+        self.last_rtp_timestamp = Some(header.timestamp);
+        self.last_seq = Some(header.seq);
+    }
+
+    pub fn observe_sender_report(&mut self, report: SenderReport) {
+        let entry = SenderReportEntry::from(report);
+
+        if self.first_sender_report.is_none() {
+            self.first_sender_report = Some(entry.clone());
+        }
+
+        self.last_sender_report = Some(entry);
+    }
+
+    pub fn observe_reorder_stats(&mut self, stats: ReorderStats) {
+        self.duplicate_packets_count += stats.duplicates;
+        self.late_packets_count += stats.late;
+    }
+
+    /// Marks the segment as finished and records its size.
+    /// The checksum is calculated separately since it takes reading the whole file.
+    pub fn finish(&mut self, dir: &str, end_time: i64) -> Result<()> {
+        self.state = SegmentState::Completed;
+        self.end_time = Some(end_time);
+
+        let path = Path::new(dir).join(&self.filename);
+        let metadata = fs::metadata(&path)
+            .with_context(|| format!("Failed to stat {}", path.to_string_lossy()))?;
+
+        self.size = Some(metadata.len());
+        Ok(())
+    }
+}
+
+/// Calculates SHA-256 of the file.
+pub fn checksum(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.to_string_lossy()))?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context("Failed to calculate checksum")?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SegmentEntry, SequenceGap};
+
+    fn packet(seq: u16, timestamp: u32) -> Vec<u8> {
+        let mut packet = vec![0x80, 0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x10, 0x00];
+        packet[2..4].copy_from_slice(&seq.to_be_bytes());
+        packet[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn detect_gaps() {
+        let mut entry = SegmentEntry::new(0, String::from("0_0.audio.mjr"), "opus", 0);
+
+        for (seq, timestamp) in &[(65533, 100), (65534, 200), (1, 500), (0, 400), (1, 500)] {
+            entry.observe_packet(&packet(*seq, *timestamp));
+        }
+
+        entry.observe_packet(&packet(5, 900));
+
+        assert_eq!(entry.packets_count, 6);
+        assert_eq!(entry.first_rtp_timestamp, Some(100));
+        assert_eq!(entry.last_rtp_timestamp, Some(900));
+        assert_eq!(entry.lost_packets_count, 5);
+
+        assert_eq!(
+            entry.gaps,
+            vec![
+                SequenceGap {
+                    after_seq: 65534,
+                    missing: 2
+                },
+                SequenceGap {
+                    after_seq: 1,
+                    missing: 3
+                },
+            ]
+        );
+    }
+}
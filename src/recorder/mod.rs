@@ -0,0 +1,1290 @@
+use std::{
+    collections::hash_map::Entry,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use std::{error::Error as StdError, time::Duration};
+use std::{fmt, time::Instant};
+use std::{fs, io};
+
+use anyhow::{bail, Context, Error, Result};
+use chrono::{DateTime, Utc};
+use fnv::{FnvHashMap, FnvHashSet};
+use once_cell::sync::OnceCell;
+
+use crate::switchboard::StreamId;
+use crate::{
+    janus_recorder::{Codec, JanusRecorder},
+    metrics::Metrics,
+    rtp::{self, RtpHeader, SenderReport},
+};
+
+pub use self::encryption::EncryptionConfig;
+pub use self::events::{Media, StreamEvent, EVENTS_FILENAME};
+pub use self::janitor::{clean_records, free_space};
+pub use self::manifest::MANIFEST_FILENAME;
+pub use self::queue::OverflowPolicy;
+pub use self::records::{list_records, RecordInfo};
+pub use self::recovery::Recovery;
+pub use self::reorder::ReorderStats;
+pub use self::upload::{upload_script_path, LiveUpload, SegmentUploader};
+pub use self::volumes::{PlacementPolicy, Volumes};
+
+use self::buffer_pool::{BufferPool, PooledBuffer};
+use self::encryption::{DataKey, ENCRYPTED_EXTENSION};
+use self::events::append_event;
+use self::manifest::{Manifest, SegmentEntry, SharedManifest};
+use self::queue::{DroppedPacket, RecorderQueue};
+use self::records::upload_lock_filename;
+use self::reorder::ReorderBuffer;
+
+mod buffer_pool;
+mod encryption;
+mod events;
+mod janitor;
+mod manifest;
+mod queue;
+mod records;
+mod recovery;
+mod reorder;
+mod upload;
+mod volumes;
+
+/// How long to wait for a video keyframe to rotate a full segment on.
+/// When exceeded the segment gets rotated on an arbitrary packet.
+const MAX_KEYFRAME_WAIT: Duration = Duration::from_secs(10);
+
+/// Minimal interval between packets of the same media to log a pause event.
+const MIN_PAUSE_DURATION: Duration = Duration::from_secs(2);
+
+/// Media of a stream to record.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordMedia {
+    Audio,
+    Video,
+    Both,
+}
+
+impl Default for RecordMedia {
+    fn default() -> Self {
+        Self::Both
+    }
+}
+
+impl RecordMedia {
+    pub fn from_flags(has_audio: bool, has_video: bool) -> Option<Self> {
+        match (has_audio, has_video) {
+            (true, true) => Some(Self::Both),
+            (true, false) => Some(Self::Audio),
+            (false, true) => Some(Self::Video),
+            (false, false) => None,
+        }
+    }
+
+    pub fn includes(self, is_video: bool) -> bool {
+        match self {
+            Self::Both => true,
+            Self::Audio => !is_video,
+            Self::Video => is_video,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct Config {
+    /// A single recordings directory. Ignored if `directories` are specified.
+    #[serde(default)]
+    pub directory: String,
+    /// Recordings directories on different disks to place stream records dirs on.
+    #[serde(default)]
+    pub directories: Vec<String>,
+    /// How to choose a directory for a new stream out of `directories`.
+    #[serde(default)]
+    pub placement: PlacementPolicy,
+    pub enabled: bool,
+    pub delete_records: bool,
+    /// Close the current record segment and start a new one after this duration.
+    #[serde(default, with = "humantime_serde")]
+    pub max_segment_duration: Option<Duration>,
+    /// Close the current record segment and start a new one after this number of bytes written.
+    #[serde(default)]
+    pub max_segment_bytes: Option<u64>,
+    /// Maximum number of RTP packets waiting to be written.
+    #[serde(default = "Config::default_queue_capacity")]
+    pub queue_capacity: usize,
+    /// What to do with packets that don't fit the queue.
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+    /// Number of recorder threads. Streams are distributed among them by id.
+    #[serde(default = "Config::default_shards")]
+    pub shards: usize,
+    /// Maximum number of packets per media held to put them in sequence number order.
+    #[serde(default = "Config::default_reorder_depth")]
+    pub reorder_depth: usize,
+    /// Maximum time a packet is held waiting for the preceding ones.
+    #[serde(default = "Config::default_reorder_latency", with = "humantime_serde")]
+    pub reorder_latency: Duration,
+    /// Delete records older than this.
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    /// Delete the oldest records when the total size of records exceeds this number of bytes.
+    #[serde(default)]
+    pub max_total_size: Option<u64>,
+    /// Refuse to start new recordings when free disk space is below this number of bytes.
+    #[serde(default)]
+    pub min_free_space: Option<u64>,
+    /// How often to apply the retention policy and update disk usage metrics.
+    #[serde(default = "Config::default_janitor_interval", with = "humantime_serde")]
+    pub janitor_interval: Duration,
+    /// Encrypt closed segments with a per-stream data key wrapped by the master key.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+}
+
+impl Config {
+    fn default_janitor_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_reorder_depth() -> usize {
+        32
+    }
+
+    fn default_reorder_latency() -> Duration {
+        Duration::from_millis(100)
+    }
+
+    fn default_shards() -> usize {
+        4
+    }
+
+    fn default_queue_capacity() -> usize {
+        10_000
+    }
+
+    /// Returns all the recordings directories.
+    pub fn volumes(&self) -> Vec<String> {
+        if self.directories.is_empty() {
+            vec![self.directory.clone()]
+        } else {
+            self.directories.clone()
+        }
+    }
+
+    pub fn check(&mut self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        if self.directory.is_empty() && self.directories.is_empty() {
+            bail!("Recordings: neither directory nor directories are specified");
+        }
+
+        for directory in self.volumes() {
+            if !Path::new(&directory).exists() {
+                bail!(
+                    "Recordings: recordings directory {} does not exist",
+                    directory
+                );
+            }
+        }
+
+        if let Some(encryption) = &self.encryption {
+            encryption
+                .check()
+                .context("Recordings: invalid encryption config")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum RecorderMsg {
+    Stop {
+        stream_id: StreamId,
+    },
+    Packet {
+        buf: PooledBuffer,
+        is_video: bool,
+        stream_id: StreamId,
+    },
+    SenderReport {
+        report: SenderReport,
+        is_video: bool,
+        stream_id: StreamId,
+    },
+    Event {
+        event: StreamEvent,
+        time: DateTime<Utc>,
+        stream_id: StreamId,
+    },
+    Start {
+        stream_id: StreamId,
+        dir: String,
+        start_time: DateTime<Utc>,
+        live_upload: Option<LiveUpload>,
+        media: RecordMedia,
+    },
+    WaitStop {
+        waiter: async_oneshot::Sender<()>,
+        stream_id: StreamId,
+    },
+}
+
+#[derive(Debug)]
+pub struct RecorderHandlesCreator {
+    queues: Vec<Arc<RecorderQueue>>,
+    buffer_pool: Arc<BufferPool>,
+    config: Config,
+    uploader: SegmentUploader,
+    volumes: Arc<Volumes>,
+    recovery: Arc<Recovery>,
+}
+
+impl RecorderHandlesCreator {
+    fn new(
+        queues: Vec<Arc<RecorderQueue>>,
+        buffer_pool: Arc<BufferPool>,
+        config: Config,
+        uploader: SegmentUploader,
+    ) -> Self {
+        // Records left by a previous run must be collected before any recording starts.
+        let recovery = if config.enabled {
+            Recovery::scan(&config.volumes())
+        } else {
+            Recovery::default()
+        };
+
+        Self {
+            queues,
+            buffer_pool,
+            volumes: Arc::new(Volumes::new(&config)),
+            recovery: Arc::new(recovery),
+            config,
+            uploader,
+        }
+    }
+
+    pub fn uploader(&self) -> &SegmentUploader {
+        &self.uploader
+    }
+
+    pub fn volumes(&self) -> &Volumes {
+        &self.volumes
+    }
+
+    pub fn recovery(&self) -> &Recovery {
+        &self.recovery
+    }
+
+    /// Creates a handle sending messages to the shard serving the stream.
+    /// All the messages of a stream go to the same shard so they are processed in order.
+    pub fn new_handle(&self, stream_id: StreamId) -> RecorderHandle {
+        let shard = (stream_id.as_u128() % self.queues.len() as u128) as usize;
+        RecorderHandle::new(self, stream_id, shard)
+    }
+}
+
+/// A recorder thread serving a shard of streams.
+pub struct Recorder {
+    shard: usize,
+    messages: Arc<RecorderQueue>,
+    metrics_update_interval: Duration,
+    config: Config,
+    uploader: SegmentUploader,
+}
+
+impl Recorder {
+    fn new(
+        shard: usize,
+        messages: Arc<RecorderQueue>,
+        metrics_update_interval: Duration,
+        config: Config,
+        uploader: SegmentUploader,
+    ) -> Self {
+        Self {
+            shard,
+            messages,
+            metrics_update_interval,
+            config,
+            uploader,
+        }
+    }
+
+    pub fn start(self) {
+        let mut recorders = FnvHashMap::default();
+        let mut now = Instant::now();
+        let mut waiters: FnvHashMap<_, Vec<async_oneshot::Sender<()>>> = FnvHashMap::default();
+        loop {
+            let msg = self.messages.pop();
+            if now.elapsed() > self.metrics_update_interval {
+                Metrics::observe_recorder(
+                    self.shard,
+                    recorders.len(),
+                    self.messages.len(),
+                    waiters.len(),
+                );
+                now = Instant::now();
+            }
+
+            match msg {
+                RecorderMsg::Stop { stream_id } => {
+                    if let Err(err) = self.handle_stop(&mut recorders, stream_id).context("Stop") {
+                        err!("Recording stopping error: {:?}", err; {"rtc_id": stream_id});
+                    } else {
+                        info!("Recording stopped"; {"rtc_id": stream_id});
+                    }
+                    if let Some(waiters) = waiters.remove(&stream_id) {
+                        for mut waiter in waiters {
+                            let _ = waiter.send(());
+                        }
+                    }
+                }
+                RecorderMsg::Packet {
+                    buf,
+                    is_video,
+                    stream_id,
+                } => {
+                    if let Err(err) = self
+                        .handle_packet(&mut recorders, stream_id, buf, is_video)
+                        .context("Packet")
+                    {
+                        err!("Failed to record frame: {:?}", err; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::SenderReport {
+                    report,
+                    is_video,
+                    stream_id,
+                } => {
+                    if let Err(err) =
+                        Self::handle_sender_report(&mut recorders, stream_id, report, is_video)
+                            .context("SenderReport")
+                    {
+                        err!("Failed to record sender report: {:?}", err; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::Event {
+                    event,
+                    time,
+                    stream_id,
+                } => {
+                    if let Err(err) =
+                        Self::handle_event(&recorders, stream_id, &event, time).context("Event")
+                    {
+                        err!("Failed to record event: {:?}", err; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::Start {
+                    dir,
+                    stream_id,
+                    start_time,
+                    live_upload,
+                    media,
+                } => {
+                    if let Err(err) = self
+                        .handle_start(
+                            &mut recorders,
+                            stream_id,
+                            &dir,
+                            start_time,
+                            live_upload,
+                            media,
+                        )
+                        .context("Start")
+                    {
+                        err!("Failed to create recorders: {:?}", err; {"rtc_id": stream_id})
+                    } else {
+                        info!("Recording to {}", dir; {"rtc_id": stream_id});
+                    }
+                }
+                RecorderMsg::WaitStop {
+                    mut waiter,
+                    stream_id,
+                } => {
+                    if recorders.contains_key(&stream_id) {
+                        waiters
+                            .entry(stream_id)
+                            .or_insert_with(Vec::new)
+                            .push(waiter);
+                    } else {
+                        let _ = waiter.send(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_stop(
+        &self,
+        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        stream_id: StreamId,
+    ) -> Result<()> {
+        if let Some(recorders) = recorders.remove(&stream_id) {
+            self.close_recorders(recorders, stream_id)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the last segment, calculates its checksum and encrypts it if the encryption is enabled.
+    /// It's not uploaded live since `stream.upload` is expected to ship it.
+    fn close_recorders(&self, mut recorders: Recorders<'_>, stream_id: StreamId) -> Result<()> {
+        let closed_segment_filenames = recorders.close()?;
+
+        for filename in closed_segment_filenames {
+            self.uploader.finish_segment(
+                stream_id,
+                &recorders.dir,
+                filename,
+                &recorders.manifest,
+                None,
+                recorders.data_key.clone(),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn handle_packet(
+        &self,
+        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        stream_id: StreamId,
+        packet: PooledBuffer,
+        is_video: bool,
+    ) -> Result<()> {
+        let recorders = recorders
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        if !recorders.media.includes(is_video) {
+            return Ok(());
+        }
+
+        recorders.detect_pause(is_video)?;
+        let now = Instant::now();
+
+        match RtpHeader::parse(as_bytes(&packet)) {
+            Some(header) => recorders
+                .reorder_buffer(is_video)
+                .push(header.seq, packet, now),
+            None => return self.write_packet(recorders, stream_id, &packet, is_video),
+        }
+
+        while let Some(packet) = recorders.reorder_buffer(is_video).pop(now) {
+            self.write_packet(recorders, stream_id, &packet, is_video)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_packet(
+        &self,
+        recorders: &mut Recorders<'_>,
+        stream_id: StreamId,
+        packet: &[i8],
+        is_video: bool,
+    ) -> Result<()> {
+        if recorders.rotation_requested_at.is_none() && recorders.segment.is_full(&self.config) {
+            recorders.rotation_requested_at = Some(Instant::now());
+        }
+
+        // Start the new segment from a keyframe so it could be played back independently.
+        // Without video recorded there's no keyframe to wait for.
+        if let Some(requested_at) = recorders.rotation_requested_at {
+            let is_keyframe = is_video && rtp::is_vp8_keyframe(as_bytes(packet));
+            let is_video_recorded = recorders.media.includes(true);
+
+            if is_keyframe || !is_video_recorded || requested_at.elapsed() >= MAX_KEYFRAME_WAIT {
+                self.rotate_segment(recorders, stream_id)
+                    .context("Segment rotation")?;
+            }
+        }
+
+        recorders.segment.save_frame(packet, is_video)
+    }
+
+    fn handle_sender_report(
+        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        stream_id: StreamId,
+        report: SenderReport,
+        is_video: bool,
+    ) -> Result<()> {
+        let recorders = recorders
+            .get_mut(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        if let Some(track) = recorders.segment.track_mut(is_video) {
+            track.entry.observe_sender_report(report);
+        }
+
+        Ok(())
+    }
+
+    fn handle_event(
+        recorders: &FnvHashMap<StreamId, Recorders<'_>>,
+        stream_id: StreamId,
+        event: &StreamEvent,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        let recorders = recorders
+            .get(&stream_id)
+            .ok_or_else(|| anyhow!("Recorders missing"))?;
+
+        append_event(&recorders.dir, time.timestamp_millis(), event)
+    }
+
+    /// Closes the current segment and starts recording to a new one in the same dir.
+    /// The closed segment gets its checksum calculated, then uploaded right away if the live upload
+    /// is enabled for the stream and then encrypted if the encryption is enabled.
+    fn rotate_segment(&self, recorders: &mut Recorders<'_>, stream_id: StreamId) -> Result<()> {
+        let closed_segment_filenames = recorders.rotate()?;
+
+        info!(
+            "Record segment rotated; recording to segment {}", recorders.segment.index;
+            {"rtc_id": stream_id}
+        );
+
+        for filename in closed_segment_filenames {
+            self.uploader.finish_segment(
+                stream_id,
+                &recorders.dir,
+                filename,
+                &recorders.manifest,
+                recorders.live_upload.clone(),
+                recorders.data_key.clone(),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn handle_start(
+        &self,
+        recorders: &mut FnvHashMap<StreamId, Recorders<'_>>,
+        stream_id: StreamId,
+        dir: &str,
+        start_time: DateTime<Utc>,
+        live_upload: Option<LiveUpload>,
+        media: RecordMedia,
+    ) -> Result<()> {
+        Self::create_records_dir(dir)?;
+        let new_recorders = Recorders::create(dir, start_time, live_upload, media, &self.config)?;
+
+        match recorders.entry(stream_id) {
+            Entry::Occupied(mut e) => {
+                let v = e.insert(new_recorders);
+                self.close_recorders(v, stream_id)
+            }
+            Entry::Vacant(e) => {
+                e.insert(new_recorders);
+                Ok(())
+            }
+        }
+    }
+
+    fn create_records_dir(dir: &str) -> Result<(), std::io::Error> {
+        if let Err(err) = fs::create_dir(dir) {
+            match err.kind() {
+                std::io::ErrorKind::AlreadyExists => Ok(()),
+                _ => Err(err),
+            }
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Recording state of a single stream.
+struct Recorders<'a> {
+    dir: String,
+    manifest: SharedManifest,
+    segment: Segment<'a>,
+    media: RecordMedia,
+    rotation_requested_at: Option<Instant>,
+    live_upload: Option<LiveUpload>,
+    last_audio_packet_at: Option<DateTime<Utc>>,
+    last_video_packet_at: Option<DateTime<Utc>>,
+    audio_reorder: ReorderBuffer<PooledBuffer>,
+    video_reorder: ReorderBuffer<PooledBuffer>,
+    data_key: Option<Arc<DataKey>>,
+}
+
+impl<'a> Recorders<'a> {
+    fn create(
+        dir: &str,
+        start_time: DateTime<Utc>,
+        live_upload: Option<LiveUpload>,
+        media: RecordMedia,
+        config: &Config,
+    ) -> Result<Self> {
+        // The manifest may be left from previous recordings, e.g. before Janus restart.
+        let manifest = SharedManifest::load(dir)?;
+
+        // Continue segment numbering after the segments left from previous recordings.
+        let index = manifest.read(|manifest| {
+            manifest
+                .segments
+                .iter()
+                .map(|segment| segment.index + 1)
+                .max()
+                .unwrap_or(0)
+        })?;
+
+        let data_key = match &config.encryption {
+            Some(encryption) => Some(Arc::new(DataKey::load_or_create(dir, encryption)?)),
+            None => None,
+        };
+
+        let segment = Segment::create(dir, index, start_time, media)?;
+        manifest.update(|manifest| segment.register(manifest))?;
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            manifest,
+            segment,
+            media,
+            rotation_requested_at: None,
+            live_upload,
+            last_audio_packet_at: None,
+            last_video_packet_at: None,
+            audio_reorder: ReorderBuffer::new(config.reorder_depth, config.reorder_latency),
+            video_reorder: ReorderBuffer::new(config.reorder_depth, config.reorder_latency),
+            data_key,
+        })
+    }
+
+    fn reorder_buffer(&mut self, is_video: bool) -> &mut ReorderBuffer<PooledBuffer> {
+        if is_video {
+            &mut self.video_reorder
+        } else {
+            &mut self.audio_reorder
+        }
+    }
+
+    /// Moves reorder buffers stats collected during the current segment to its entries.
+    fn take_reorder_stats(&mut self) {
+        let audio_stats = self.audio_reorder.take_stats();
+        let video_stats = self.video_reorder.take_stats();
+        Metrics::observe_recorder_reorder(audio_stats);
+        Metrics::observe_recorder_reorder(video_stats);
+
+        if let Some(track) = self.segment.track_mut(false) {
+            track.entry.observe_reorder_stats(audio_stats);
+        }
+
+        if let Some(track) = self.segment.track_mut(true) {
+            track.entry.observe_reorder_stats(video_stats);
+        }
+    }
+
+    /// Logs a pause event when the packet arrives too late after the previous one of the same media.
+    fn detect_pause(&mut self, is_video: bool) -> Result<()> {
+        let now = Utc::now();
+
+        let last_packet_at = if is_video {
+            &mut self.last_video_packet_at
+        } else {
+            &mut self.last_audio_packet_at
+        };
+
+        let prev = match last_packet_at.replace(now) {
+            Some(prev) => prev,
+            None => return Ok(()),
+        };
+
+        let is_pause = (now - prev)
+            .to_std()
+            .map(|duration| duration >= MIN_PAUSE_DURATION)
+            .unwrap_or(false);
+
+        if is_pause {
+            let event = StreamEvent::Pause {
+                media: Media::new(is_video),
+                duration: (now - prev).num_milliseconds(),
+            };
+
+            append_event(&self.dir, prev.timestamp_millis(), &event)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the current segment with a new one and returns filenames of the closed segment.
+    fn rotate(&mut self) -> Result<Vec<String>> {
+        self.take_reorder_stats();
+        let next = Segment::create(&self.dir, self.segment.index + 1, Utc::now(), self.media)?;
+        let mut prev = std::mem::replace(&mut self.segment, next);
+        self.rotation_requested_at = None;
+
+        let close_result = prev.close(&self.dir);
+        let segment = &self.segment;
+
+        self.manifest.update(|manifest| {
+            prev.register(manifest);
+            segment.register(manifest);
+        })?;
+
+        close_result?;
+
+        Ok(prev.filenames())
+    }
+
+    /// Closes the current segment and returns its filenames.
+    fn close(&mut self) -> Result<Vec<String>> {
+        // Write out packets still waiting for the missing ones.
+        while let Some(packet) = self.audio_reorder.drain() {
+            self.segment.save_frame(&packet, false)?;
+        }
+
+        while let Some(packet) = self.video_reorder.drain() {
+            self.segment.save_frame(&packet, true)?;
+        }
+
+        self.take_reorder_stats();
+        let close_result = self.segment.close(&self.dir);
+        let segment = &self.segment;
+        self.manifest
+            .update(|manifest| segment.register(manifest))?;
+        close_result?;
+        Ok(self.segment.filenames())
+    }
+}
+
+/// Audio & video recorders writing the current segment of a stream record.
+/// Both files of the segment share the same start time and index in their names.
+/// Media not selected for recording has no recorder.
+struct Segment<'a> {
+    index: usize,
+    audio: Option<Track<'a>>,
+    video: Option<Track<'a>>,
+    started_at: Instant,
+    bytes_written: u64,
+}
+
+/// A recorder of a single media of the segment along with its manifest entry.
+struct Track<'a> {
+    recorder: JanusRecorder<'a>,
+    entry: SegmentEntry,
+}
+
+impl<'a> Track<'a> {
+    fn create(
+        dir: &str,
+        filename: &str,
+        codec: Codec,
+        index: usize,
+        start_time: i64,
+    ) -> Result<Self> {
+        let recorder = JanusRecorder::create(dir, filename, codec)?;
+        let entry = SegmentEntry::new(index, recorder.filename(), codec.as_str(), start_time);
+        Ok(Self { recorder, entry })
+    }
+}
+
+impl<'a> Segment<'a> {
+    fn create(
+        dir: &str,
+        index: usize,
+        start_time: DateTime<Utc>,
+        media: RecordMedia,
+    ) -> Result<Self> {
+        let start_time = start_time.timestamp_millis();
+        let basename = format!("{}_{}", start_time, index);
+
+        let video = if media.includes(true) {
+            let filename = format!("{}.video", basename);
+            Some(Track::create(
+                dir,
+                &filename,
+                Codec::VP8,
+                index,
+                start_time,
+            )?)
+        } else {
+            None
+        };
+
+        let audio = if media.includes(false) {
+            let filename = format!("{}.audio", basename);
+            Some(Track::create(
+                dir,
+                &filename,
+                Codec::Opus,
+                index,
+                start_time,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            index,
+            audio,
+            video,
+            started_at: Instant::now(),
+            bytes_written: 0,
+        })
+    }
+
+    fn track_mut(&mut self, is_video: bool) -> Option<&mut Track<'a>> {
+        if is_video {
+            self.video.as_mut()
+        } else {
+            self.audio.as_mut()
+        }
+    }
+
+    fn tracks_mut(&mut self) -> impl Iterator<Item = &mut Track<'a>> {
+        self.audio.iter_mut().chain(self.video.iter_mut())
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &SegmentEntry> {
+        self.audio
+            .iter()
+            .chain(self.video.iter())
+            .map(|track| &track.entry)
+    }
+
+    fn filenames(&self) -> Vec<String> {
+        self.entries().map(|entry| entry.filename.clone()).collect()
+    }
+
+    fn register(&self, manifest: &mut Manifest) {
+        for entry in self.entries() {
+            manifest.upsert(entry);
+        }
+    }
+
+    fn save_frame(&mut self, packet: &[i8], is_video: bool) -> Result<()> {
+        if let Some(track) = self.track_mut(is_video) {
+            track.recorder.save_frame(packet)?;
+            track.entry.observe_packet(as_bytes(packet));
+            self.bytes_written += packet.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    fn is_full(&self, config: &Config) -> bool {
+        let is_too_long = config
+            .max_segment_duration
+            .map(|max_duration| self.started_at.elapsed() >= max_duration)
+            .unwrap_or(false);
+
+        let is_too_big = config
+            .max_segment_bytes
+            .map(|max_bytes| self.bytes_written >= max_bytes)
+            .unwrap_or(false);
+
+        is_too_long || is_too_big
+    }
+
+    /// Closes all the recorders and finalizes their entries returning the first error if any.
+    fn close(&mut self, dir: &str) -> Result<()> {
+        let mut result = Ok(());
+
+        for track in self.tracks_mut() {
+            if let Err(err) = track.recorder.close() {
+                result = result.and(Err(err));
+            }
+        }
+
+        let end_time = Utc::now().timestamp_millis();
+
+        for track in self.tracks_mut() {
+            if let Err(err) = track.entry.finish(dir, end_time) {
+                result = result.and(Err(err));
+            }
+        }
+
+        result
+    }
+}
+
+fn as_bytes(packet: &[i8]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(packet.as_ptr() as *const u8, packet.len()) }
+}
+
+/// Creates recorders for each of the configured shards to be started in separate threads.
+pub fn recorder(
+    config: Config,
+    metrics: crate::conf::Metrics,
+) -> (Vec<Recorder>, RecorderHandlesCreator) {
+    let uploader = SegmentUploader::new();
+    let mut queues = Vec::with_capacity(config.shards);
+    let mut recorders = Vec::with_capacity(config.shards);
+
+    for shard in 0..config.shards.max(1) {
+        let queue = Arc::new(RecorderQueue::new(
+            config.queue_capacity,
+            config.overflow_policy,
+        ));
+
+        recorders.push(Recorder::new(
+            shard,
+            queue.clone(),
+            metrics.recorders_metrics_load_interval,
+            config.clone(),
+            uploader.clone(),
+        ));
+
+        queues.push(queue);
+    }
+
+    // Packets being queued never exceed the queues capacity so there's no point to keep more.
+    let buffer_pool = BufferPool::new(config.queue_capacity * queues.len());
+
+    (
+        recorders,
+        RecorderHandlesCreator::new(queues, buffer_pool, config, uploader),
+    )
+}
+
+#[derive(Debug)]
+pub struct RecorderHandle {
+    shard: usize,
+    queue: Arc<RecorderQueue>,
+    buffer_pool: Arc<BufferPool>,
+    uploader: SegmentUploader,
+    is_overflowed: AtomicBool,
+    stream_id: StreamId,
+    volumes: Arc<Volumes>,
+    /// The volume the stream records dir has been placed on.
+    volume: OnceCell<String>,
+    recovery: Arc<Recovery>,
+    encryption: Option<EncryptionConfig>,
+
+    is_deletion_enabled: bool,
+}
+
+/// Records video from RTP stream identified by `stream_id`.
+///
+/// `stream_id` is used as a directory for parts of a record.
+/// In case of Janus restart stream newly created recorder
+/// for old stream resumes recording but writes to new file
+/// in that directory. Filename for record part is generated
+/// by the following rule: `unix_timestamp_index.extension`
+/// where `index` is the sequential number of the part within the directory.
+///
+/// When `max_segment_duration` or `max_segment_bytes` is configured the recorder
+/// also closes the current part and starts a new one on the next video keyframe
+/// once the threshold is reached.
+/// With live upload enabled closed parts get uploaded while the stream is still ongoing.
+///
+/// Packets are queued up to `queue_capacity`. On overflow they get dropped
+/// according to `overflow_policy` so a stalled disk doesn't exhaust memory.
+///
+/// With multiple recordings directories configured the stream dir is placed on one of them
+/// by `placement` policy and found there afterwards.
+///
+/// With encryption enabled closed parts are encrypted with the stream's data key
+/// and `decrypt_segments` restores plaintext ones for the upload.
+///
+/// Config changes, takeovers, pauses and speaking changes are logged
+/// to `events.jsonl` in the same directory.
+///
+/// Recorders run in separate threads each serving its shard of streams.
+/// You're able to write buffers using `record_packet` method.
+impl RecorderHandle {
+    fn new(creator: &RecorderHandlesCreator, stream_id: StreamId, shard: usize) -> Self {
+        let volume = OnceCell::new();
+
+        if let Some(path) = creator.volumes.locate(stream_id) {
+            let _ = volume.set(path.to_owned());
+        }
+
+        Self {
+            shard,
+            stream_id,
+            volumes: creator.volumes.clone(),
+            volume,
+            recovery: creator.recovery.clone(),
+            encryption: creator.config.encryption.clone(),
+            is_deletion_enabled: creator.config.delete_records,
+            queue: creator.queues[shard].clone(),
+            buffer_pool: creator.buffer_pool.clone(),
+            uploader: creator.uploader.clone(),
+            is_overflowed: AtomicBool::new(false),
+        }
+    }
+
+    fn send(&self, msg: RecorderMsg) {
+        if let Some(dropped) = self.queue.push(msg) {
+            self.handle_dropped_packet(dropped);
+        }
+    }
+
+    fn handle_dropped_packet(&self, dropped: DroppedPacket) {
+        Metrics::observe_recorder_dropped_packet(self.shard, dropped.is_video);
+
+        if self.queue.policy() != OverflowPolicy::StopRecording
+            || dropped.stream_id != self.stream_id
+            || self.is_overflowed.swap(true, Ordering::Relaxed)
+        {
+            return;
+        }
+
+        err!("Recorder queue overflowed; stopping recording"; {"rtc_id": self.stream_id});
+
+        self.queue.push(RecorderMsg::Event {
+            event: StreamEvent::QueueOverflow,
+            time: Utc::now(),
+            stream_id: self.stream_id,
+        });
+
+        self.queue.push(RecorderMsg::Stop {
+            stream_id: self.stream_id,
+        });
+    }
+
+    pub fn record_packet(&self, buf: &[i8], is_video: bool) -> Result<()> {
+        // The recording has been stopped because of the queue overflow.
+        if self.is_overflowed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        // The buffer gets back to the pool once the packet is written or dropped.
+        self.send(RecorderMsg::Packet {
+            buf: self.buffer_pool.copy_from(buf),
+            is_video,
+            stream_id: self.stream_id,
+        });
+
+        Ok(())
+    }
+
+    pub fn record_sender_report(&self, report: SenderReport, is_video: bool) -> Result<()> {
+        self.send(RecorderMsg::SenderReport {
+            report,
+            is_video,
+            stream_id: self.stream_id,
+        });
+
+        Ok(())
+    }
+
+    /// Appends the event to the stream's event log with the current wallclock time.
+    pub fn record_event(&self, event: StreamEvent) -> Result<()> {
+        self.send(RecorderMsg::Event {
+            event,
+            time: Utc::now(),
+            stream_id: self.stream_id,
+        });
+
+        Ok(())
+    }
+
+    pub fn start_recording(
+        &self,
+        live_upload: Option<LiveUpload>,
+        media: RecordMedia,
+    ) -> Result<()> {
+        info!("Start recording {:?}", media; {"rtc_id": self.stream_id});
+
+        let volume = self
+            .volume
+            .get_or_try_init(|| self.volumes.place(self.stream_id).map(String::from))?;
+
+        let dir = Path::new(volume).join(self.stream_id.to_string());
+
+        if self.recovery.is_pending(&dir) {
+            bail!("Records of the stream left by the previous run are being recovered");
+        }
+
+        let dir = dir.to_string_lossy().into_owned();
+        self.is_overflowed.store(false, Ordering::Relaxed);
+
+        self.send(RecorderMsg::Start {
+            stream_id: self.stream_id,
+            dir,
+            start_time: Utc::now(),
+            live_upload,
+            media,
+        });
+
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) -> Result<()> {
+        self.send(RecorderMsg::Stop {
+            stream_id: self.stream_id,
+        });
+
+        Ok(())
+    }
+
+    pub async fn wait_stop(&self) -> Result<()> {
+        let (tx, rx) = async_oneshot::oneshot();
+
+        self.send(RecorderMsg::WaitStop {
+            waiter: tx,
+            stream_id: self.stream_id,
+        });
+
+        let _ = rx.await;
+        Ok(())
+    }
+
+    /// Waits for segments closed during the recording to finish uploading.
+    pub async fn wait_segment_uploads(&self) {
+        self.uploader.wait(self.stream_id).await
+    }
+
+    /// Checks whether the record is being uploaded either live or by `stream.upload`.
+    pub async fn is_uploading(&self) -> bool {
+        let is_uploading_live = self.uploader.uploading_streams().contains(&self.stream_id);
+
+        is_uploading_live
+            || self
+                .get_records_dir()
+                .join(upload_lock_filename(self.stream_id))
+                .exists()
+    }
+
+    pub fn is_deletion_enabled(&self) -> bool {
+        self.is_deletion_enabled
+    }
+
+    /// Whether the records left by the previous run are still being recovered.
+    pub fn is_recovering(&self) -> bool {
+        self.recovery.is_pending(&self.get_records_dir())
+    }
+
+    /// Returns the stream records dir on the volume it's been placed on.
+    /// If there's no such dir yet it's a path on the first volume.
+    pub fn get_records_dir(&self) -> PathBuf {
+        let volume = match self.volume.get() {
+            Some(volume) => volume.as_str(),
+            None => self
+                .volumes
+                .locate(self.stream_id)
+                .unwrap_or(&self.volumes.paths()[0]),
+        };
+
+        Path::new(volume).join(self.stream_id.to_string())
+    }
+
+    /// Decrypts segments which haven't been uploaded live for `upload_record.sh`
+    /// and returns paths of the plaintext files. Encrypted files are kept.
+    pub async fn decrypt_segments(&self) -> Result<Vec<PathBuf>> {
+        let encryption = match &self.encryption {
+            Some(encryption) => encryption.clone(),
+            None => return Ok(vec![]),
+        };
+
+        let dir = self.get_records_dir();
+        async_std::task::spawn_blocking(move || decrypt_segments(&dir, &encryption)).await
+    }
+
+    pub fn check_existence(&self) -> Result<()> {
+        let path = self.get_records_dir();
+        let metadata = fs::metadata(&path).context("Record doesn't exist")?;
+
+        if metadata.is_dir() {
+            Ok(())
+        } else {
+            bail!(
+                "Recording path {} is not a directory",
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    pub fn delete_record(&self) -> Result<()> {
+        if self.is_deletion_enabled {
+            fs::remove_dir_all(self.get_records_dir()).context("Failed to delete record")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Decrypts segments of the records dir which aren't listed in `uploaded.txt`.
+fn decrypt_segments(dir: &Path, encryption: &EncryptionConfig) -> Result<Vec<PathBuf>> {
+    let uploaded = match fs::read_to_string(dir.join("uploaded.txt")) {
+        Ok(uploaded) => uploaded,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context("Failed to read uploaded.txt"),
+    };
+
+    let uploaded = uploaded
+        .lines()
+        .filter_map(|line| line.split(' ').next())
+        .collect::<FnvHashSet<_>>();
+
+    let encrypted_suffix = format!(".mjr.{}", ENCRYPTED_EXTENSION);
+    let mut data_key = None;
+    let mut paths = Vec::new();
+
+    for entry in fs::read_dir(dir).context("Failed to read records dir")? {
+        let path = entry.context("Failed to read records dir")?.path();
+
+        let filename = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => match name.strip_suffix(&encrypted_suffix) {
+                Some(basename) => format!("{}.mjr", basename),
+                None => continue,
+            },
+            None => continue,
+        };
+
+        if uploaded.contains(filename.as_str()) {
+            continue;
+        }
+
+        let data_key = match &data_key {
+            Some(data_key) => data_key,
+            None => data_key.insert(DataKey::load_or_create(&dir.to_string_lossy(), encryption)?),
+        };
+
+        let plaintext_path = dir.join(&filename);
+
+        data_key
+            .decrypt_file(&plaintext_path)
+            .with_context(|| format!("Failed to decrypt {}", filename))?;
+
+        paths.push(plaintext_path);
+    }
+
+    Ok(paths)
+}
+
+#[derive(Debug)]
+pub enum RecorderError {
+    InternalError(Error),
+    IoError(io::Error),
+}
+
+impl fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InternalError(source) => write!(f, "{}", source),
+            Self::IoError(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl StdError for RecorderError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::InternalError(source) => Some(source.as_ref()),
+            Self::IoError(source) => Some(source),
+        }
+    }
+}
+
+impl From<Error> for RecorderError {
+    fn from(err: Error) -> RecorderError {
+        RecorderError::InternalError(err)
+    }
+}
+
+impl From<io::Error> for RecorderError {
+    fn from(err: io::Error) -> RecorderError {
+        RecorderError::IoError(err)
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+};
+
+use super::RecorderMsg;
+use crate::switchboard::StreamId;
+
+/// What to do with RTP packets when the recorder queue is full.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop video packets keeping audio as long as possible.
+    DropVideoFirst,
+    /// Drop the oldest queued packet in favour of the new one.
+    DropOldest,
+    /// Drop the packet and stop recording of the stream it belongs to.
+    StopRecording,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        Self::DropVideoFirst
+    }
+}
+
+/// A packet which didn't make it to the recorder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedPacket {
+    pub stream_id: StreamId,
+    pub is_video: bool,
+}
+
+/// Recorder message queue bounded by the number of RTP packets.
+///
+/// Control messages like start or stop are always accepted and never dropped
+/// so they don't get lost or reordered with respect to packets when the disk stalls.
+///
+/// Messages are kept apart by kind so a packet to drop is always at the front of its queue
+/// and the order is restored on pop by sequence numbers.
+#[derive(Debug)]
+pub struct RecorderQueue {
+    state: Mutex<State>,
+    available: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    control: VecDeque<(u64, RecorderMsg)>,
+    audio: VecDeque<(u64, RecorderMsg)>,
+    video: VecDeque<(u64, RecorderMsg)>,
+    next_seq: u64,
+}
+
+impl State {
+    fn packets_count(&self) -> usize {
+        self.audio.len() + self.video.len()
+    }
+
+    fn push(&mut self, msg: RecorderMsg) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let queue = match as_packet(&msg) {
+            Some(packet) if packet.is_video => &mut self.video,
+            Some(_) => &mut self.audio,
+            None => &mut self.control,
+        };
+
+        queue.push_back((seq, msg));
+    }
+
+    fn pop(&mut self) -> Option<RecorderMsg> {
+        let queue = IntoIterator::into_iter([&mut self.control, &mut self.audio, &mut self.video])
+            .filter(|queue| !queue.is_empty())
+            .min_by_key(|queue| queue.front().map(|(seq, _)| *seq))?;
+
+        queue.pop_front().map(|(_, msg)| msg)
+    }
+
+    fn pop_oldest_packet(&mut self) -> Option<RecorderMsg> {
+        let is_video_older = match (self.audio.front(), self.video.front()) {
+            (Some((audio_seq, _)), Some((video_seq, _))) => video_seq < audio_seq,
+            (None, Some(_)) => true,
+            _ => false,
+        };
+
+        let queue = match is_video_older {
+            true => &mut self.video,
+            false => &mut self.audio,
+        };
+
+        queue.pop_front().map(|(_, msg)| msg)
+    }
+}
+
+impl RecorderQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            available: Condvar::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Enqueues the message and returns the packet dropped to fit the capacity if any.
+    /// It may be either the pushed packet or a queued one depending on the policy.
+    pub fn push(&self, msg: RecorderMsg) -> Option<DroppedPacket> {
+        let mut state = self.state.lock().expect("Recorder queue lock poisoned");
+        let mut dropped = None;
+
+        if let Some(packet) = as_packet(&msg) {
+            if state.packets_count() >= self.capacity {
+                let queued = match self.policy {
+                    OverflowPolicy::DropVideoFirst if !packet.is_video => {
+                        state.video.pop_front().map(|(_, msg)| msg)
+                    }
+                    OverflowPolicy::DropOldest => state.pop_oldest_packet(),
+                    _ => None,
+                };
+
+                match queued {
+                    Some(queued) => dropped = as_packet(&queued),
+                    None => return Some(packet),
+                }
+            }
+        }
+
+        state.push(msg);
+        self.available.notify_one();
+        dropped
+    }
+
+    /// Waits for the next message.
+    pub fn pop(&self) -> RecorderMsg {
+        let mut state = self.state.lock().expect("Recorder queue lock poisoned");
+
+        loop {
+            if let Some(msg) = state.pop() {
+                return msg;
+            }
+
+            state = self
+                .available
+                .wait(state)
+                .expect("Recorder queue lock poisoned");
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().expect("Recorder queue lock poisoned");
+        state.control.len() + state.packets_count()
+    }
+}
+
+fn as_packet(msg: &RecorderMsg) -> Option<DroppedPacket> {
+    match msg {
+        RecorderMsg::Packet {
+            stream_id,
+            is_video,
+            ..
+        } => Some(DroppedPacket {
+            stream_id: *stream_id,
+            is_video: *is_video,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{DroppedPacket, OverflowPolicy, RecorderQueue};
+    use crate::recorder::{buffer_pool::BufferPool, RecorderMsg};
+    use crate::switchboard::StreamId;
+
+    fn packet(stream_id: StreamId, is_video: bool, byte: i8) -> RecorderMsg {
+        RecorderMsg::Packet {
+            buf: BufferPool::new(0).copy_from(&[byte]),
+            is_video,
+            stream_id,
+        }
+    }
+
+    fn pop_packet(queue: &RecorderQueue) -> (bool, i8) {
+        match queue.pop() {
+            RecorderMsg::Packet { buf, is_video, .. } => (is_video, buf[0]),
+            msg => panic!("Expected packet, got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn drop_video_first() {
+        let stream_id = Uuid::new_v4();
+        let queue = RecorderQueue::new(2, OverflowPolicy::DropVideoFirst);
+        assert_eq!(queue.push(packet(stream_id, true, 1)), None);
+        assert_eq!(queue.push(packet(stream_id, false, 2)), None);
+
+        // Incoming video gets dropped.
+        assert_eq!(
+            queue.push(packet(stream_id, true, 3)),
+            Some(DroppedPacket {
+                stream_id,
+                is_video: true
+            })
+        );
+
+        // Incoming audio replaces queued video.
+        assert_eq!(
+            queue.push(packet(stream_id, false, 4)),
+            Some(DroppedPacket {
+                stream_id,
+                is_video: true
+            })
+        );
+
+        // Nothing to replace so incoming audio gets dropped.
+        assert_eq!(
+            queue.push(packet(stream_id, false, 5)),
+            Some(DroppedPacket {
+                stream_id,
+                is_video: false
+            })
+        );
+
+        assert_eq!(pop_packet(&queue), (false, 2));
+        assert_eq!(pop_packet(&queue), (false, 4));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_control_messages() {
+        let stream_id = Uuid::new_v4();
+        let queue = RecorderQueue::new(1, OverflowPolicy::DropOldest);
+        assert_eq!(queue.push(RecorderMsg::Stop { stream_id }), None);
+        assert_eq!(queue.push(packet(stream_id, true, 1)), None);
+        assert!(queue.push(packet(stream_id, false, 2)).is_some());
+        assert!(matches!(queue.pop(), RecorderMsg::Stop { .. }));
+        assert_eq!(pop_packet(&queue), (false, 2));
+
+        // The capacity is released on pop.
+        assert_eq!(queue.push(packet(stream_id, true, 3)), None);
+    }
+
+    #[test]
+    fn keep_order_across_media() {
+        let stream_id = Uuid::new_v4();
+        let queue = RecorderQueue::new(3, OverflowPolicy::DropOldest);
+        assert_eq!(queue.push(packet(stream_id, false, 1)), None);
+        assert_eq!(queue.push(RecorderMsg::Stop { stream_id }), None);
+        assert_eq!(queue.push(packet(stream_id, true, 2)), None);
+        assert_eq!(queue.push(packet(stream_id, false, 3)), None);
+
+        // The oldest one is audio this time.
+        assert_eq!(
+            queue.push(packet(stream_id, true, 4)),
+            Some(DroppedPacket {
+                stream_id,
+                is_video: false
+            })
+        );
+
+        assert!(matches!(queue.pop(), RecorderMsg::Stop { .. }));
+        assert_eq!(pop_packet(&queue), (true, 2));
+        assert_eq!(pop_packet(&queue), (false, 3));
+        assert_eq!(pop_packet(&queue), (true, 4));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn stop_recording_drops_incoming() {
+        let stream_id = Uuid::new_v4();
+        let queue = RecorderQueue::new(1, OverflowPolicy::StopRecording);
+        assert_eq!(queue.push(packet(stream_id, false, 1)), None);
+        assert!(queue.push(packet(stream_id, false, 2)).is_some());
+        assert_eq!(pop_packet(&queue), (false, 1));
+    }
+}
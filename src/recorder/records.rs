@@ -0,0 +1,233 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fnv::FnvHashSet;
+
+use super::encryption::ENCRYPTED_EXTENSION;
+use super::manifest::{Manifest, SegmentState};
+use crate::switchboard::StreamId;
+
+/// A stream records dir found in the recordings directory.
+#[derive(Debug, Serialize)]
+pub struct RecordInfo {
+    pub id: StreamId,
+    /// Recordings directory the record is placed in.
+    pub volume: String,
+    pub state: RecordState,
+    pub upload_state: UploadState,
+    pub segments_count: usize,
+    /// Total size of the files in the dir in bytes.
+    pub size: u64,
+    /// Unix timestamp in milliseconds of the first segment start.
+    pub created_at: i64,
+    /// Seconds since `created_at`.
+    pub age: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordState {
+    Recording,
+    Completed,
+    /// Some of the segments have been interrupted by a crash.
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadState {
+    NotUploaded,
+    /// Some segments have been uploaded live but `stream.upload` hasn't been called yet.
+    PartiallyUploaded,
+    Uploading,
+    /// `upload_record.sh` has shipped all the segments.
+    Uploaded,
+}
+
+/// Lists stream records dirs in the recordings directories.
+///
+/// `is_recording` and `is_uploading` tell about the in-memory state of a stream
+/// which can't be derived from the files.
+pub fn list_records(
+    volumes: &[String],
+    is_recording: impl Fn(StreamId) -> bool,
+    is_uploading: impl Fn(StreamId) -> bool,
+) -> Result<Vec<RecordInfo>> {
+    let mut records = Vec::new();
+
+    // An unavailable volume mustn't hide records on the others.
+    for volume in volumes {
+        if let Err(err) = list_volume_records(volume, &is_recording, &is_uploading, &mut records) {
+            err!("Failed to list records in {}: {:?}", volume, err);
+        }
+    }
+
+    records.sort_by_key(|record| record.created_at);
+    Ok(records)
+}
+
+fn list_volume_records(
+    volume: &str,
+    is_recording: impl Fn(StreamId) -> bool,
+    is_uploading: impl Fn(StreamId) -> bool,
+    records: &mut Vec<RecordInfo>,
+) -> Result<()> {
+    let entries = fs::read_dir(volume)
+        .with_context(|| format!("Failed to read recordings directory {}", volume))?;
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                err!("Failed to read recordings directory {}: {:?}", volume, err);
+                continue;
+            }
+        };
+
+        let id = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if path.is_dir() => match name.parse::<StreamId>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+
+        match read_record_info(volume, &path, id, is_recording(id), is_uploading(id)) {
+            Ok(info) => records.push(info),
+            Err(err) => err!("Failed to read record {}: {:?}", id, err; {"rtc_id": id}),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_record_info(
+    volume: &str,
+    path: &Path,
+    id: StreamId,
+    is_recording: bool,
+    is_uploading: bool,
+) -> Result<RecordInfo> {
+    let manifest = Manifest::load(&path.to_string_lossy())?;
+    let mut size = 0;
+    let mut mjr_files_count = 0;
+    let mut created_at = None;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += metadata.len();
+
+        let filename = entry.file_name();
+        let filename = filename.to_string_lossy();
+
+        if filename.ends_with(".mjr")
+            || filename.ends_with(&format!(".mjr.{}", ENCRYPTED_EXTENSION))
+        {
+            mjr_files_count += 1;
+        }
+
+        if let Ok(modified_at) = metadata.modified() {
+            let modified_at = DateTime::<Utc>::from(modified_at).timestamp_millis();
+            created_at = Some(created_at.map_or(modified_at, |c: i64| c.min(modified_at)));
+        }
+    }
+
+    // Records made before the manifest was introduced have only files.
+    let segments_count = if manifest.segments.is_empty() {
+        mjr_files_count / 2
+    } else {
+        manifest
+            .segments
+            .iter()
+            .map(|segment| segment.index)
+            .collect::<FnvHashSet<_>>()
+            .len()
+    };
+
+    if let Some(start_time) = manifest.segments.iter().map(|s| s.start_time).min() {
+        created_at = Some(start_time);
+    }
+
+    let created_at = created_at.unwrap_or_else(|| Utc::now().timestamp_millis());
+
+    let is_interrupted = manifest.segments.iter().any(|segment| {
+        matches!(
+            segment.state,
+            SegmentState::Recovered | SegmentState::Interrupted
+        )
+    });
+
+    let state = if is_recording {
+        RecordState::Recording
+    } else if is_interrupted {
+        RecordState::Interrupted
+    } else {
+        RecordState::Completed
+    };
+
+    // See `upload_record.sh` for the files it leaves in the dir. It starts `dumps.txt`
+    // before the upload so the file is left by a failed one too.
+    let upload_state = if is_uploading || path.join(upload_lock_filename(id)).exists() {
+        UploadState::Uploading
+    } else if path.join(UPLOAD_DONE_FILENAME).exists() {
+        UploadState::Uploaded
+    } else if path.join("dumps.txt").exists() || path.join("uploaded.txt").exists() {
+        UploadState::PartiallyUploaded
+    } else {
+        UploadState::NotUploaded
+    };
+
+    Ok(RecordInfo {
+        id,
+        volume: volume.to_owned(),
+        state,
+        upload_state,
+        segments_count,
+        size,
+        created_at,
+        age: (Utc::now().timestamp_millis() - created_at) / 1000,
+    })
+}
+
+/// The marker `upload_record.sh` leaves in the records dir after a successful upload.
+const UPLOAD_DONE_FILENAME: &str = "upload_done";
+
+/// The lock file `upload_record.sh` keeps in the records dir while uploading.
+pub fn upload_lock_filename(id: StreamId) -> String {
+    format!("vacuum_{}.lock", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::{list_records, RecordState, UploadState};
+
+    #[test]
+    fn list_record_dirs() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let id = Uuid::new_v4();
+        let dir = root.join(id.to_string());
+        fs::create_dir_all(&dir).expect("Failed to create dir");
+        fs::create_dir(root.join("not_a_stream")).expect("Failed to create dir");
+
+        for filename in &["1_0.mjr", "2_0.mjr", "uploaded.txt"] {
+            fs::write(dir.join(filename), b"1234").expect("Failed to write file");
+        }
+
+        let volumes = [root.to_string_lossy().into_owned()];
+        let records = list_records(&volumes, |_| false, |_| false);
+        fs::remove_dir_all(&root).expect("Failed to remove dir");
+        let records = records.expect("Failed to list records");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, id);
+        assert_eq!(records[0].state, RecordState::Completed);
+        assert_eq!(records[0].upload_state, UploadState::PartiallyUploaded);
+        assert_eq!(records[0].segments_count, 1);
+        assert_eq!(records[0].size, 12);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fnv::FnvHashSet;
+
+use super::encryption::{DataKey, EncryptionConfig};
+use super::manifest::{checksum, Manifest, SegmentState};
+
+const MJR_MAGIC: &[u8] = b"MJR00002";
+const MJR_FRAME_MARKER: &[u8] = b"MEET";
+
+/// Records dirs left by a previous run which are still to be recovered.
+///
+/// The dirs are collected on startup before any recording starts since at that moment
+/// every segment still marked as being recorded has been interrupted by a crash.
+/// The recovery itself runs in background and a stream can't be recorded again
+/// until its dir is recovered.
+#[derive(Debug, Default)]
+pub struct Recovery {
+    pending: Mutex<FnvHashSet<PathBuf>>,
+}
+
+impl Recovery {
+    /// Collects records dirs on the volumes.
+    pub fn scan(volumes: &[String]) -> Self {
+        let mut pending = FnvHashSet::default();
+
+        for volume in volumes {
+            match list_dirs(volume) {
+                Ok(dirs) => pending.extend(dirs),
+                Err(err) => err!("Failed to recover records in {}: {:?}", volume, err),
+            }
+        }
+
+        Self {
+            pending: Mutex::new(pending),
+        }
+    }
+
+    pub fn is_pending(&self, dir: &Path) -> bool {
+        self.pending
+            .lock()
+            .expect("Recovery lock poisoned")
+            .contains(dir)
+    }
+
+    /// Repairs segments which haven't been closed and releases the dirs one by one.
+    /// With encryption enabled segments left in plaintext get encrypted.
+    pub fn recover(&self, encryption: Option<&EncryptionConfig>) {
+        let dirs = self
+            .pending
+            .lock()
+            .expect("Recovery lock poisoned")
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for path in dirs {
+            let dir = path.to_string_lossy();
+
+            match recover_dir(&dir) {
+                Ok(0) => (),
+                Ok(count) => warn!("Recovered {} interrupted record segments in {}", count, dir),
+                Err(err) => err!("Failed to recover records in {}: {:?}", dir, err),
+            }
+
+            if let Some(encryption) = encryption {
+                if let Err(err) = encrypt_dir(&dir, encryption) {
+                    err!("Failed to encrypt records in {}: {:?}", dir, err);
+                }
+            }
+
+            self.pending
+                .lock()
+                .expect("Recovery lock poisoned")
+                .remove(&path);
+        }
+    }
+}
+
+fn list_dirs(root: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+
+    for entry in fs::read_dir(root).context("Failed to read recordings directory")? {
+        let path = entry.context("Failed to read recordings directory")?.path();
+
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+
+    Ok(dirs)
+}
+
+/// Repairs dangling segments of the manifest in the dir and returns their number.
+fn recover_dir(dir: &str) -> Result<usize> {
+    let mut manifest = Manifest::load(dir)?;
+    let mut count = 0;
+
+    for entry in manifest.segments.iter_mut() {
+        if entry.state != SegmentState::Recording && entry.end_time.is_some() {
+            continue;
+        }
+
+        let path = Path::new(dir).join(&entry.filename);
+
+        let state = match repair_mjr(&path) {
+            Ok(0) => SegmentState::Interrupted,
+            Ok(_frames) => SegmentState::Recovered,
+            Err(err) => {
+                err!("Failed to repair {}: {:?}", path.to_string_lossy(), err);
+                SegmentState::Interrupted
+            }
+        };
+
+        // The file was last written right before the crash.
+        let end_time = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| DateTime::<Utc>::from(modified).timestamp_millis())
+            .unwrap_or(entry.start_time);
+
+        let finish_result = entry
+            .finish(dir, end_time)
+            .and_then(|()| checksum(&path))
+            .map(|sha256| entry.sha256 = Some(sha256));
+
+        if let Err(err) = finish_result {
+            err!("Failed to finish {}: {:?}", path.to_string_lossy(), err);
+        }
+
+        entry.state = state;
+        count += 1;
+    }
+
+    if count > 0 {
+        manifest.save(dir)?;
+    }
+
+    Ok(count)
+}
+
+/// Encrypts the segments of the manifest in the dir which are still in plaintext.
+fn encrypt_dir(dir: &str, encryption: &EncryptionConfig) -> Result<()> {
+    let manifest = Manifest::load(dir)?;
+    let mut data_key = None;
+
+    for entry in &manifest.segments {
+        let path = Path::new(dir).join(&entry.filename);
+
+        if !path.exists() {
+            continue;
+        }
+
+        let data_key = match &data_key {
+            Some(data_key) => data_key,
+            None => data_key.insert(DataKey::load_or_create(dir, encryption)?),
+        };
+
+        data_key
+            .encrypt_file(&path)
+            .with_context(|| format!("Failed to encrypt {}", entry.filename))?;
+    }
+
+    Ok(())
+}
+
+/// Truncates a trailing partially written frame of an MJR file and returns the number of
+/// complete frames in it. The file is left as is if it's not an MJR file of a known version.
+///
+/// The layout is `MJR00002`, u16 info header length, JSON info header and then frames each of
+/// `MEET`, u32 timestamp, u16 length and the RTP packet itself. Numbers are big endian.
+fn repair_mjr(path: &Path) -> Result<usize> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .context("Failed to open file")?;
+
+    let mut reader = BufReader::new(&file);
+    let mut magic = [0; 8];
+
+    if !read_full(&mut reader, &mut magic)? || magic != MJR_MAGIC {
+        return Ok(0);
+    }
+
+    let mut info_len = [0; 2];
+
+    if !read_full(&mut reader, &mut info_len)? {
+        return Ok(0);
+    }
+
+    let info_len = u16::from_be_bytes(info_len) as i64;
+    let mut offset = (MJR_MAGIC.len() + 2) as u64 + info_len as u64;
+    let mut frames = 0;
+
+    if file.metadata()?.len() >= offset {
+        reader.seek_relative(info_len)?;
+
+        loop {
+            let mut header = [0; 10];
+
+            if !read_full(&mut reader, &mut header)? || &header[..4] != MJR_FRAME_MARKER {
+                break;
+            }
+
+            let len = u16::from_be_bytes([header[8], header[9]]) as u64;
+            let frame_end = offset + header.len() as u64 + len;
+
+            if frame_end > file.metadata()?.len() {
+                break;
+            }
+
+            reader.seek(SeekFrom::Start(frame_end))?;
+            offset = frame_end;
+            frames += 1;
+        }
+    }
+
+    let size = file.metadata()?.len();
+
+    if offset < size {
+        warn!(
+            "Truncating {} trailing bytes of {}",
+            size - offset,
+            path.to_string_lossy()
+        );
+
+        file.set_len(offset).context("Failed to truncate file")?;
+    }
+
+    Ok(frames)
+}
+
+/// Fills the buffer and returns `false` if the reader ended before that.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{repair_mjr, Recovery};
+
+    #[test]
+    fn release_recovered_dirs() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let dir = root.join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).expect("Failed to create dir");
+
+        let recovery = Recovery::scan(&[root.to_string_lossy().into_owned()]);
+        let is_pending_before = recovery.is_pending(&dir);
+        recovery.recover(None);
+        let is_pending_after = recovery.is_pending(&dir);
+        fs::remove_dir_all(&root).expect("Failed to remove dir");
+
+        assert!(is_pending_before);
+        assert!(!is_pending_after);
+    }
+
+    #[test]
+    fn truncate_partial_frame() {
+        let path = std::env::temp_dir().join(format!("{}.mjr", uuid::Uuid::new_v4()));
+        let info = br#"{"t":"a","c":"opus"}"#;
+
+        let mut content = b"MJR00002".to_vec();
+        content.extend_from_slice(&(info.len() as u16).to_be_bytes());
+        content.extend_from_slice(info);
+
+        for ts in 0..2u32 {
+            content.extend_from_slice(b"MEET");
+            content.extend_from_slice(&(ts * 20).to_be_bytes());
+            content.extend_from_slice(&3u16.to_be_bytes());
+            content.extend_from_slice(&[1, 2, 3]);
+        }
+
+        let complete_len = content.len() as u64;
+        content.extend_from_slice(b"MEET");
+        content.extend_from_slice(&40u32.to_be_bytes());
+        content.extend_from_slice(&3u16.to_be_bytes());
+        content.push(1);
+        fs::write(&path, &content).expect("Failed to write file");
+
+        let frames = repair_mjr(&path).expect("Failed to repair");
+        let len = fs::metadata(&path).expect("Failed to get metadata").len();
+        fs::remove_file(&path).expect("Failed to remove file");
+        assert_eq!(frames, 2);
+        assert_eq!(len, complete_len);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// Offset for extended sequence numbers so they never go below zero
+/// when early packets arrive after the first one.
+const EXTENDED_SEQ_BASE: u64 = 1 << 32;
+
+/// Puts RTP packets of a single media back in sequence number order.
+///
+/// A packet is held until the packets preceding it arrive, the buffer grows over `depth`
+/// or the packet waits longer than `latency`. Missing packets are given up then.
+#[derive(Debug)]
+pub struct ReorderBuffer<T> {
+    packets: BTreeMap<u64, (T, Instant)>,
+    depth: usize,
+    latency: Duration,
+    highest_seq: Option<u64>,
+    next_seq: Option<u64>,
+    stats: ReorderStats,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReorderStats {
+    /// Packets given up waiting for.
+    pub lost: u64,
+    /// Packets received more than once while waiting in the buffer.
+    pub duplicates: u64,
+    /// Packets arrived after their turn: duplicates of written packets or the ones given up.
+    pub late: u64,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(depth: usize, latency: Duration) -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            depth,
+            latency,
+            highest_seq: None,
+            next_seq: None,
+            stats: ReorderStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, seq: u16, packet: T, now: Instant) {
+        let seq = self.extend_seq(seq);
+
+        match self.next_seq {
+            Some(next_seq) if seq < next_seq => self.stats.late += 1,
+            _ if self.packets.contains_key(&seq) => self.stats.duplicates += 1,
+            _ => {
+                self.packets.insert(seq, (packet, now));
+            }
+        }
+    }
+
+    /// Returns the next packet in order if it's ready to be written.
+    pub fn pop(&mut self, now: Instant) -> Option<T> {
+        let (&seq, (_, arrived_at)) = self.packets.iter().next()?;
+
+        let is_ready = match self.next_seq {
+            None => true,
+            Some(next_seq) if next_seq == seq => true,
+            Some(_) => {
+                self.packets.len() > self.depth || now.duration_since(*arrived_at) >= self.latency
+            }
+        };
+
+        if is_ready {
+            self.take(seq)
+        } else {
+            None
+        }
+    }
+
+    /// Returns buffered packets in order regardless of the gaps.
+    pub fn drain(&mut self) -> Option<T> {
+        let seq = *self.packets.keys().next()?;
+        self.take(seq)
+    }
+
+    /// Returns the stats collected since the previous call.
+    pub fn take_stats(&mut self) -> ReorderStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    fn take(&mut self, seq: u64) -> Option<T> {
+        let (packet, _) = self.packets.remove(&seq)?;
+
+        if let Some(next_seq) = self.next_seq {
+            self.stats.lost += seq - next_seq;
+        }
+
+        self.next_seq = Some(seq + 1);
+        Some(packet)
+    }
+
+    /// Converts 16-bit sequence number to a monotonic one taking wraparounds into account.
+    fn extend_seq(&mut self, seq: u16) -> u64 {
+        let extended = match self.highest_seq {
+            None => EXTENDED_SEQ_BASE + seq as u64,
+            Some(highest_seq) => {
+                let delta = seq.wrapping_sub(highest_seq as u16) as i16;
+                (highest_seq as i64 + delta as i64) as u64
+            }
+        };
+
+        if self.highest_seq < Some(extended) {
+            self.highest_seq = Some(extended);
+        }
+
+        extended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ReorderBuffer, ReorderStats};
+
+    fn pop_all(buffer: &mut ReorderBuffer<u16>, now: Instant) -> Vec<u16> {
+        std::iter::from_fn(|| buffer.pop(now)).collect()
+    }
+
+    #[test]
+    fn reorder_packets() {
+        let now = Instant::now();
+        let mut buffer = ReorderBuffer::new(10, Duration::from_secs(1));
+
+        for seq in &[65534, 0, 65535, 1, 1, 65535] {
+            buffer.push(*seq, *seq, now);
+        }
+
+        assert_eq!(pop_all(&mut buffer, now), vec![65534, 65535, 0, 1]);
+
+        assert_eq!(
+            buffer.take_stats(),
+            ReorderStats {
+                lost: 0,
+                duplicates: 2,
+                late: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn give_up_on_latency() {
+        let now = Instant::now();
+        let mut buffer = ReorderBuffer::new(10, Duration::from_millis(100));
+        buffer.push(1, 1, now);
+        buffer.push(4, 4, now);
+        assert_eq!(pop_all(&mut buffer, now), vec![1]);
+        assert_eq!(
+            pop_all(&mut buffer, now + Duration::from_millis(50)),
+            Vec::<u16>::new()
+        );
+        assert_eq!(
+            pop_all(&mut buffer, now + Duration::from_millis(100)),
+            vec![4]
+        );
+
+        // The packet arrived too late.
+        buffer.push(2, 2, now + Duration::from_millis(150));
+        assert!(pop_all(&mut buffer, now).is_empty());
+
+        assert_eq!(
+            buffer.take_stats(),
+            ReorderStats {
+                lost: 2,
+                duplicates: 0,
+                late: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn give_up_on_depth() {
+        let now = Instant::now();
+        let mut buffer = ReorderBuffer::new(2, Duration::from_secs(1));
+        buffer.push(1, 1, now);
+        assert_eq!(pop_all(&mut buffer, now), vec![1]);
+
+        for seq in 3..6 {
+            buffer.push(seq, seq, now);
+        }
+
+        assert_eq!(pop_all(&mut buffer, now), vec![3, 4, 5]);
+        assert_eq!(buffer.take_stats().lost, 1);
+    }
+
+    #[test]
+    fn drain_remaining() {
+        let now = Instant::now();
+        let mut buffer = ReorderBuffer::new(10, Duration::from_secs(1));
+        buffer.push(1, 1, now);
+        buffer.push(3, 3, now);
+        assert_eq!(pop_all(&mut buffer, now), vec![1]);
+        assert_eq!(
+            std::iter::from_fn(|| buffer.drain()).collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(buffer.take_stats().lost, 1);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::hash_map::Entry,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::{bail, format_err, Context, Result};
+use async_std::process::Command;
+use fnv::FnvHashMap;
+
+use super::{encryption::DataKey, manifest::SharedManifest};
+use crate::switchboard::StreamId;
+
+/// Upload target for segments which get closed while the stream is still being recorded.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LiveUpload {
+    pub backend: String,
+    pub bucket: String,
+}
+
+/// Ships closed record segments to the upload backend and encrypts them in background.
+///
+/// Every segment is uploaded by `upload_record.sh` in single file mode which also
+/// registers the resulting URI in `uploaded.txt` inside the records dir. So when
+/// `stream.upload` gets called the script skips already uploaded segments and only
+/// the tail gets shipped.
+///
+/// Encryption goes after the upload so the live upload ships plaintext segments
+/// same as `stream.upload` which decrypts them beforehand.
+#[derive(Clone, Debug, Default)]
+pub struct SegmentUploader {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    pending: Mutex<FnvHashMap<StreamId, Pending>>,
+}
+
+/// Segments of a stream being processed and tasks waiting for them to finish.
+#[derive(Debug, Default)]
+struct Pending {
+    count: usize,
+    waiters: Vec<async_oneshot::Sender<()>>,
+}
+
+impl Inner {
+    fn pending(&self) -> MutexGuard<'_, FnvHashMap<StreamId, Pending>> {
+        self.pending.lock().expect("Segment uploader lock poisoned")
+    }
+}
+
+impl SegmentUploader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Calculates the checksum of the closed segment off the recorder thread, uploads it
+    /// if the live upload is enabled and then encrypts it if the data key is given.
+    pub fn finish_segment(
+        &self,
+        stream_id: StreamId,
+        dir: &str,
+        filename: String,
+        manifest: &SharedManifest,
+        target: Option<LiveUpload>,
+        data_key: Option<Arc<DataKey>>,
+    ) {
+        let inner = self.inner.clone();
+        let manifest = manifest.clone();
+        let path = Path::new(dir).join(&filename);
+        let recordings_dir = Path::new(dir).parent().map(Path::to_path_buf);
+
+        // Register the upload before spawning so `wait` called afterwards can't miss it.
+        inner.pending().entry(stream_id).or_default().count += 1;
+
+        async_std::task::spawn(async move {
+            // The checksum goes first since the encryption removes the plaintext file.
+            let checksum_filename = filename.clone();
+            let result =
+                async_std::task::spawn_blocking(move || manifest.set_checksum(&checksum_filename))
+                    .await;
+
+            if let Err(err) = result {
+                err!(
+                    "Failed to calculate checksum of segment {}: {:?}", filename, err;
+                    {"rtc_id": stream_id}
+                );
+            }
+
+            if let (Some(target), Some(recordings_dir)) = (target, recordings_dir) {
+                match upload_segment(stream_id, &recordings_dir, &filename, &target).await {
+                    Ok(()) => info!(
+                        "Segment {} uploaded to {} bucket", filename, target.bucket;
+                        {"rtc_id": stream_id}
+                    ),
+                    Err(err) => err!(
+                        "Failed to upload segment {}: {:?}", filename, err;
+                        {"rtc_id": stream_id}
+                    ),
+                }
+            }
+
+            if let Some(data_key) = data_key {
+                let result =
+                    async_std::task::spawn_blocking(move || data_key.encrypt_file(&path)).await;
+
+                if let Err(err) = result {
+                    err!(
+                        "Failed to encrypt segment {}: {:?}", filename, err;
+                        {"rtc_id": stream_id}
+                    );
+                }
+            }
+
+            if let Entry::Occupied(mut entry) = inner.pending().entry(stream_id) {
+                entry.get_mut().count -= 1;
+
+                if entry.get().count == 0 {
+                    for mut waiter in entry.remove().waiters {
+                        let _ = waiter.send(());
+                    }
+                }
+            }
+        });
+    }
+
+    /// Returns ids of streams having segment uploads or encryptions in progress.
+    pub fn uploading_streams(&self) -> Vec<StreamId> {
+        self.inner.pending().keys().copied().collect()
+    }
+
+    /// Waits until all the segment uploads of the stream are finished.
+    pub async fn wait(&self, stream_id: StreamId) {
+        let rx = match self.inner.pending().get_mut(&stream_id) {
+            Some(pending) => {
+                let (tx, rx) = async_oneshot::oneshot();
+                pending.waiters.push(tx);
+                rx
+            }
+            None => return,
+        };
+
+        let _ = rx.await;
+    }
+}
+
+async fn upload_segment(
+    stream_id: StreamId,
+    recordings_dir: &Path,
+    filename: &str,
+    target: &LiveUpload,
+) -> Result<()> {
+    let mut command = Command::new(upload_script_path()?);
+    command.env("RECORDINGS_DIR", recordings_dir);
+    let stream_id = stream_id.to_string();
+    command.args([
+        stream_id.as_str(),
+        target.backend.as_str(),
+        target.bucket.as_str(),
+        filename,
+    ]);
+
+    huge!("Running segment upload shell command: {:?}", command);
+
+    let status = command
+        .status()
+        .await
+        .with_context(|| format!("Failed to run upload_record.sh, command = '{:?}'", command))?;
+
+    if !status.success() {
+        bail!(
+            "Failed to upload segment: {}, command = '{:?}'",
+            status,
+            command
+        );
+    }
+
+    Ok(())
+}
+
+pub fn upload_script_path() -> Result<PathBuf> {
+    let mut script_path = std::env::current_exe()
+        .context("Failed to get current executable path")?
+        .parent()
+        .ok_or_else(|| format_err!("Missing current executable dir"))?
+        .to_path_buf();
+
+    script_path.push("upload_record.sh");
+    Ok(script_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use uuid::Uuid;
+
+    use super::{LiveUpload, SegmentUploader};
+    use crate::recorder::{
+        encryption::{encrypted_path, DataKey, EncryptionConfig},
+        manifest::{checksum, SegmentEntry, SharedManifest},
+    };
+
+    #[test]
+    fn calculate_checksum_without_upload_and_encryption() {
+        let stream_id = Uuid::new_v4();
+        let dir = std::env::temp_dir().join(stream_id.to_string());
+        fs::create_dir(&dir).expect("Failed to create dir");
+        let dir_str = dir.to_string_lossy().into_owned();
+        let path = dir.join("1_0.mjr");
+        fs::write(&path, b"segment").expect("Failed to write file");
+
+        let manifest = SharedManifest::load(&dir_str).expect("Failed to load manifest");
+        let entry = SegmentEntry::new(0, String::from("1_0.mjr"), "opus", 0);
+
+        manifest
+            .update(|manifest| manifest.upsert(&entry))
+            .expect("Failed to save manifest");
+
+        let uploader = SegmentUploader::new();
+        let filename = String::from("1_0.mjr");
+        uploader.finish_segment(stream_id, &dir_str, filename, &manifest, None, None);
+
+        async_std::task::block_on(async {
+            uploader.wait(stream_id).await;
+            assert!(uploader.uploading_streams().is_empty());
+        });
+
+        let sha256 = manifest
+            .read(|manifest| manifest.segments[0].sha256.clone())
+            .expect("Failed to read manifest");
+
+        let expected_sha256 = checksum(&path).expect("Failed to calculate checksum");
+        fs::remove_dir_all(&dir).expect("Failed to remove dir");
+        assert_eq!(sha256, Some(expected_sha256));
+    }
+
+    #[test]
+    fn wait_for_segments_to_finish() {
+        let stream_id = Uuid::new_v4();
+        let dir = std::env::temp_dir().join(stream_id.to_string());
+        fs::create_dir(&dir).expect("Failed to create dir");
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let config: EncryptionConfig =
+            serde_json::from_value(serde_json::json!({ "master_key": base64::encode([7; 32]) }))
+                .expect("Failed to parse config");
+
+        let key = DataKey::load_or_create(&dir_str, &config).expect("Failed to create key");
+        let manifest = SharedManifest::load(&dir_str).expect("Failed to load manifest");
+        let path = dir.join("1_0.mjr");
+        fs::write(&path, b"segment").expect("Failed to write file");
+
+        // The upload script is missing in tests so the upload fails but the segment
+        // must still get encrypted and the stream released.
+        let target = LiveUpload {
+            backend: String::from("backend"),
+            bucket: String::from("bucket"),
+        };
+
+        let uploader = SegmentUploader::new();
+        let filename = String::from("1_0.mjr");
+        uploader.finish_segment(
+            stream_id,
+            &dir_str,
+            filename,
+            &manifest,
+            Some(target),
+            Some(Arc::new(key)),
+        );
+
+        async_std::task::block_on(async {
+            uploader.wait(stream_id).await;
+            assert!(uploader.uploading_streams().is_empty());
+        });
+
+        let is_encrypted = !path.exists() && encrypted_path(&path).exists();
+        fs::remove_dir_all(&dir).expect("Failed to remove dir");
+        assert!(is_encrypted);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+};
+
+use anyhow::{Context, Result};
+use fnv::FnvHashMap;
+
+use super::janitor::free_space;
+use super::Config;
+use crate::switchboard::StreamId;
+
+/// How to choose a volume for a new stream records dir.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementPolicy {
+    /// The volume with the most free space.
+    MostFreeSpace,
+    /// Volumes in turn skipping the ones below `min_free_space`.
+    RoundRobin,
+}
+
+impl Default for PlacementPolicy {
+    fn default() -> Self {
+        Self::MostFreeSpace
+    }
+}
+
+/// Recordings directories possibly located on different disks.
+///
+/// A stream records dir is placed on one of them once and the chosen volume is remembered.
+/// Dirs left by the previous run are collected on startup so placements survive restarts.
+#[derive(Debug)]
+pub struct Volumes {
+    paths: Vec<String>,
+    policy: PlacementPolicy,
+    min_free_space: Option<u64>,
+    next: AtomicUsize,
+    /// Stream id to the index of the volume its records dir is on.
+    placements: RwLock<FnvHashMap<StreamId, usize>>,
+}
+
+impl Volumes {
+    pub fn new(config: &Config) -> Self {
+        let paths = config.volumes();
+        let placements = RwLock::new(scan_placements(&paths));
+
+        Self {
+            paths,
+            policy: config.placement,
+            min_free_space: config.min_free_space,
+            next: AtomicUsize::new(0),
+            placements,
+        }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Returns the volume the stream records dir has been placed on.
+    pub fn locate(&self, stream_id: StreamId) -> Option<&str> {
+        self.placements
+            .read()
+            .expect("Volumes placements lock poisoned")
+            .get(&stream_id)
+            .map(|index| self.paths[*index].as_str())
+    }
+
+    /// Returns the volume to record the stream to. The records dir gets created
+    /// on a volume chosen by the policy unless the stream has been placed already.
+    pub fn place(&self, stream_id: StreamId) -> Result<&str> {
+        if let Some(path) = self.locate(stream_id) {
+            return Ok(path);
+        }
+
+        let path = match self.policy {
+            PlacementPolicy::MostFreeSpace => self.most_free()?.0,
+            PlacementPolicy::RoundRobin => self.next_round_robin(),
+        };
+
+        fs::create_dir_all(Path::new(path).join(stream_id.to_string()))
+            .context("Failed to create records dir")?;
+
+        let index = self.index_of(path);
+
+        // Another handle of the same stream may have placed it meanwhile.
+        let index = *self
+            .placements
+            .write()
+            .expect("Volumes placements lock poisoned")
+            .entry(stream_id)
+            .or_insert(index);
+
+        Ok(&self.paths[index])
+    }
+
+    /// Returns free space of the volume the stream would be recorded to.
+    /// For a new stream it's the most free space among the volumes.
+    pub fn free_space_for(&self, stream_id: StreamId) -> Result<u64> {
+        match self.locate(stream_id) {
+            Some(path) => free_space(path),
+            None => Ok(self.most_free()?.1),
+        }
+    }
+
+    /// Returns the volume with the most free space skipping unavailable ones.
+    fn most_free(&self) -> Result<(&str, u64)> {
+        let mut most_free = None;
+
+        for path in &self.paths {
+            let free_space = match free_space(path) {
+                Ok(free_space) => free_space,
+                Err(err) => {
+                    err!("Failed to get free space of {}: {:?}", path, err);
+                    continue;
+                }
+            };
+
+            match most_free {
+                Some((_, max)) if max >= free_space => (),
+                _ => most_free = Some((path.as_str(), free_space)),
+            }
+        }
+
+        most_free.context("No recordings directories available")
+    }
+
+    fn index_of(&self, path: &str) -> usize {
+        self.paths
+            .iter()
+            .position(|p| p == path)
+            .expect("Path must be one of the volumes")
+    }
+
+    fn next_round_robin(&self) -> &str {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        // Fall back to plain rotation if every volume is low on space or unavailable.
+        (0..self.paths.len())
+            .map(|offset| &self.paths[(start + offset) % self.paths.len()])
+            .find(|path| match (self.min_free_space, free_space(path)) {
+                (None, _) => true,
+                (Some(min_free_space), Ok(free_space)) => free_space >= min_free_space,
+                (Some(_), Err(_)) => false,
+            })
+            .unwrap_or(&self.paths[start % self.paths.len()])
+    }
+}
+
+/// Collects stream records dirs existing on the volumes.
+fn scan_placements(paths: &[String]) -> FnvHashMap<StreamId, usize> {
+    let mut placements = FnvHashMap::default();
+
+    for (index, path) in paths.iter().enumerate() {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                err!("Failed to read recordings directory {}: {:?}", path, err);
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let stream_id = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<StreamId>().ok());
+
+            if let Some(stream_id) = stream_id {
+                if entry.path().is_dir() {
+                    placements.entry(stream_id).or_insert(index);
+                }
+            }
+        }
+    }
+
+    placements
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::{scan_placements, PlacementPolicy, Volumes};
+
+    #[test]
+    fn place_round_robin() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+
+        let paths = (0..2)
+            .map(|i| root.join(i.to_string()).to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        for path in &paths {
+            fs::create_dir_all(path).expect("Failed to create dir");
+        }
+
+        let volumes = Volumes {
+            paths: paths.clone(),
+            policy: PlacementPolicy::RoundRobin,
+            min_free_space: None,
+            next: Default::default(),
+            placements: Default::default(),
+        };
+
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let first_volume = volumes.place(first).map(String::from);
+        let second_volume = volumes.place(second).map(String::from);
+
+        // Already placed stream stays on its volume.
+        let first_volume_again = volumes.place(first).map(String::from);
+        let located = volumes.locate(second).map(String::from);
+        fs::remove_dir_all(&root).expect("Failed to remove dir");
+
+        assert_eq!(first_volume.expect("Failed to place"), paths[0]);
+        assert_eq!(second_volume.expect("Failed to place"), paths[1]);
+        assert_eq!(first_volume_again.expect("Failed to place"), paths[0]);
+        assert_eq!(located, Some(paths[1].clone()));
+    }
+
+    #[test]
+    fn place_skipping_unavailable_volume() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let missing = root.join("missing").to_string_lossy().into_owned();
+        let available = root.join("available").to_string_lossy().into_owned();
+        fs::create_dir_all(&available).expect("Failed to create dir");
+
+        let volumes = Volumes {
+            paths: vec![missing, available.clone()],
+            policy: PlacementPolicy::MostFreeSpace,
+            min_free_space: None,
+            next: Default::default(),
+            placements: Default::default(),
+        };
+
+        let volume = volumes.place(Uuid::new_v4()).map(String::from);
+        fs::remove_dir_all(&root).expect("Failed to remove dir");
+        assert_eq!(volume.expect("Failed to place"), available);
+    }
+
+    #[test]
+    fn locate_placed_by_previous_run() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+
+        let paths = (0..2)
+            .map(|i| root.join(i.to_string()).to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        let stream_id = Uuid::new_v4();
+        fs::create_dir_all(&paths[0]).expect("Failed to create dir");
+        fs::create_dir_all(root.join("1").join(stream_id.to_string()))
+            .expect("Failed to create dir");
+
+        let volumes = Volumes {
+            paths: paths.clone(),
+            policy: PlacementPolicy::MostFreeSpace,
+            min_free_space: None,
+            next: Default::default(),
+            placements: scan_placements(&paths).into(),
+        };
+
+        let located = volumes.locate(stream_id).map(String::from);
+        let placed = volumes.place(stream_id).map(String::from);
+        fs::remove_dir_all(&root).expect("Failed to remove dir");
+
+        assert_eq!(located, Some(paths[1].clone()));
+        assert_eq!(placed.expect("Failed to place"), paths[1]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Minimal parsing of raw RTP packets which doesn't need the Janus core.
+
+const RTP_HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpHeader {
+    pub seq: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    payload_offset: usize,
+}
+
+impl RtpHeader {
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < RTP_HEADER_SIZE || packet[0] >> 6 != 2 {
+            return None;
+        }
+
+        let csrc_count = (packet[0] & 0x0f) as usize;
+        let has_extension = packet[0] & 0x10 != 0;
+        let mut payload_offset = RTP_HEADER_SIZE + csrc_count * 4;
+
+        if has_extension {
+            let ext = packet.get(payload_offset..payload_offset + 4)?;
+            let ext_len = u16::from_be_bytes([ext[2], ext[3]]) as usize;
+            payload_offset += 4 + ext_len * 4;
+        }
+
+        if payload_offset > packet.len() {
+            return None;
+        }
+
+        Some(Self {
+            seq: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+            payload_offset,
+        })
+    }
+
+    pub fn payload<'a>(&self, packet: &'a [u8]) -> &'a [u8] {
+        &packet[self.payload_offset..]
+    }
+}
+
+/// Seconds between NTP (1900) and Unix (1970) epochs.
+const NTP_UNIX_EPOCH_OFFSET: u64 = 2_208_988_800;
+const RTCP_SR_PACKET_TYPE: u8 = 200;
+const RTCP_SR_SIZE: usize = 28;
+
+/// NTP ↔ RTP timestamps mapping from an RTCP sender report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderReport {
+    pub ntp_timestamp: u64,
+    pub rtp_timestamp: u32,
+}
+
+impl SenderReport {
+    /// Finds a sender report in a compound RTCP packet.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let mut offset = 0;
+
+        while let Some(header) = packet.get(offset..offset + 4) {
+            if header[0] >> 6 != 2 {
+                return None;
+            }
+
+            let size = (u16::from_be_bytes([header[2], header[3]]) as usize + 1) * 4;
+
+            if header[1] == RTCP_SR_PACKET_TYPE && size >= RTCP_SR_SIZE {
+                let report = packet.get(offset..offset + RTCP_SR_SIZE)?;
+                let mut ntp_timestamp = [0; 8];
+                ntp_timestamp.copy_from_slice(&report[8..16]);
+                let mut rtp_timestamp = [0; 4];
+                rtp_timestamp.copy_from_slice(&report[16..20]);
+
+                return Some(Self {
+                    ntp_timestamp: u64::from_be_bytes(ntp_timestamp),
+                    rtp_timestamp: u32::from_be_bytes(rtp_timestamp),
+                });
+            }
+
+            offset += size;
+        }
+
+        None
+    }
+
+    /// NTP timestamp of the report converted to Unix timestamp in milliseconds.
+    pub fn unix_timestamp_millis(&self) -> i64 {
+        let seconds = (self.ntp_timestamp >> 32).saturating_sub(NTP_UNIX_EPOCH_OFFSET);
+        let millis = ((self.ntp_timestamp & 0xffff_ffff) * 1000) >> 32;
+        (seconds * 1000 + millis) as i64
+    }
+}
+
+/// Checks whether the RTP packet starts a VP8 keyframe.
+///
+/// See https://tools.ietf.org/html/rfc7741#section-4.2 for the payload descriptor layout.
+pub fn is_vp8_keyframe(packet: &[u8]) -> bool {
+    let payload = match RtpHeader::parse(packet) {
+        Some(header) => header.payload(packet),
+        None => return false,
+    };
+
+    let descriptor = match payload.first() {
+        Some(descriptor) => *descriptor,
+        None => return false,
+    };
+
+    let is_start_of_partition = descriptor & 0x10 != 0;
+    let partition_id = descriptor & 0x0f;
+
+    if !is_start_of_partition || partition_id != 0 {
+        return false;
+    }
+
+    let mut offset = 1;
+
+    if descriptor & 0x80 != 0 {
+        let extension = match payload.get(offset) {
+            Some(extension) => *extension,
+            None => return false,
+        };
+
+        offset += 1;
+
+        // PictureID is either 7 or 15 bits long depending on the M bit.
+        if extension & 0x80 != 0 {
+            match payload.get(offset) {
+                Some(picture_id) if picture_id & 0x80 != 0 => offset += 2,
+                Some(_) => offset += 1,
+                None => return false,
+            }
+        }
+
+        // TL0PICIDX.
+        if extension & 0x40 != 0 {
+            offset += 1;
+        }
+
+        // TID/Y/KEYIDX.
+        if extension & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+
+    // Inverse keyframe flag of the VP8 payload header.
+    match payload.get(offset) {
+        Some(header) => header & 0x01 == 0,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_vp8_keyframe, RtpHeader, SenderReport};
+
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            0x80, 0x60, 0x12, 0x34, 0x00, 0x00, 0x10, 0x00, 0xde, 0xad, 0xbe, 0xef,
+        ];
+
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn parse_header() {
+        let packet = packet(&[0x10, 0x00]);
+        let header = RtpHeader::parse(&packet).expect("Failed to parse header");
+        assert_eq!(header.seq, 0x1234);
+        assert_eq!(header.timestamp, 0x1000);
+        assert_eq!(header.ssrc, 0xdeadbeef);
+        assert_eq!(header.payload(&packet), &[0x10, 0x00]);
+    }
+
+    #[test]
+    fn parse_header_with_extension() {
+        let mut packet = packet(&[0xbe, 0xde, 0x00, 0x01, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00]);
+        packet[0] |= 0x10;
+        let header = RtpHeader::parse(&packet).expect("Failed to parse header");
+        assert_eq!(header.payload(&packet), &[0x10, 0x00]);
+    }
+
+    #[test]
+    fn detect_vp8_keyframe() {
+        // Start of partition, no extensions, P bit is not set.
+        assert!(is_vp8_keyframe(&packet(&[0x10, 0x00])));
+        // P bit is set so it's an interframe.
+        assert!(!is_vp8_keyframe(&packet(&[0x10, 0x01])));
+        // Not a start of a partition.
+        assert!(!is_vp8_keyframe(&packet(&[0x00, 0x00])));
+        // Extended descriptor with 15 bit PictureID.
+        assert!(is_vp8_keyframe(&packet(&[0x90, 0x80, 0x81, 0x23, 0x00])));
+        // Extended descriptor with 7 bit PictureID, TL0PICIDX and KEYIDX.
+        assert!(is_vp8_keyframe(&packet(&[
+            0x90, 0xd0, 0x01, 0x02, 0x03, 0x00
+        ])));
+        assert!(!is_vp8_keyframe(&packet(&[
+            0x90, 0xd0, 0x01, 0x02, 0x03, 0x01
+        ])));
+        // Truncated packet.
+        assert!(!is_vp8_keyframe(&packet(&[0x90])));
+    }
+
+    #[test]
+    fn parse_sender_report() {
+        // Receiver report with no report blocks followed by a sender report.
+        let mut packet = vec![0x80, 0xc9, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01];
+        packet.extend_from_slice(&[0x80, 0xc8, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01]);
+
+        // 2021-01-01T00:00:00.500Z
+        let ntp_seconds: u32 = 1_609_459_200 + 2_208_988_800;
+        packet.extend_from_slice(&ntp_seconds.to_be_bytes());
+        packet.extend_from_slice(&0x8000_0000_u32.to_be_bytes());
+        packet.extend_from_slice(&90000_u32.to_be_bytes());
+        packet.extend_from_slice(&[0; 8]);
+
+        let report = SenderReport::parse(&packet).expect("Failed to parse sender report");
+        assert_eq!(report.rtp_timestamp, 90000);
+        assert_eq!(report.unix_timestamp_millis(), 1_609_459_200_500);
+
+        // Truncated sender report.
+        assert_eq!(SenderReport::parse(&packet[..20]), None);
+    }
+}
@@ -0,0 +1,78 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use fnv::{FnvHashMap, FnvHasher};
+
+/// An immutable map split into shards.
+///
+/// A new version with some entries changed copies only the shards having them
+/// and shares the rest with the previous version so it stays cheap for large maps.
+#[derive(Debug)]
+pub struct ShardedMap<K, V> {
+    shards: Vec<Arc<FnvHashMap<K, Arc<V>>>>,
+}
+
+impl<K, V> ShardedMap<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Arc::default()).collect(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.shards[self.shard(key)].get(key).map(Arc::as_ref)
+    }
+
+    /// Returns a new version of the map with `changes` applied. `None` removes the entry.
+    pub fn with_changes(&self, changes: impl IntoIterator<Item = (K, Option<V>)>) -> Self {
+        let mut shards = self.shards.clone();
+
+        for (key, value) in changes {
+            let shard = Arc::make_mut(&mut shards[self.shard(&key)]);
+
+            match value {
+                Some(value) => shard.insert(key, Arc::new(value)),
+                None => shard.remove(&key),
+            };
+        }
+
+        Self { shards }
+    }
+
+    fn shard(&self, key: &K) -> usize {
+        let mut hasher = FnvHasher::default();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ShardedMap;
+
+    #[test]
+    fn share_unchanged_shards() {
+        let map = ShardedMap::new(16).with_changes((0..100u64).map(|key| (key, Some(key))));
+        let next = map.with_changes(vec![(1, Some(10)), (2, None)]);
+
+        assert_eq!(map.get(&1), Some(&1));
+        assert_eq!(map.get(&2), Some(&2));
+        assert_eq!(next.get(&1), Some(&10));
+        assert_eq!(next.get(&2), None);
+        assert_eq!(next.get(&3), Some(&3));
+
+        let shared = map
+            .shards
+            .iter()
+            .zip(&next.shards)
+            .filter(|(prev, next)| Arc::ptr_eq(prev, next))
+            .count();
+
+        assert!(shared >= 14);
+    }
+}
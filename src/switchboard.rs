@@ -1,21 +1,26 @@
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::thread;
 use std::{fmt, usize};
 use std::{
-    sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, format_err, Result};
+use anyhow::{format_err, Result};
+use arc_swap::{ArcSwap, ArcSwapOption, Guard};
 use chrono::{DateTime, NaiveDateTime, Utc};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
+use http::StatusCode;
 use janus::session::SessionWrapper;
 use once_cell::sync::Lazy;
 use uuid::Uuid;
 
-use crate::conf::SwitchboardConfig;
+use crate::conf::{General, SwitchboardConfig};
 use crate::janus_rtp::JanusRtpSwitchingContext;
-use crate::recorder::RecorderHandle;
+use crate::message_handler::{send_session_evicted_notification, send_stream_state_notification};
+use crate::metrics::Metrics;
+use crate::recorder::{RecorderHandle, StreamEvent};
+use crate::sharded_map::ShardedMap;
 use crate::{bidirectional_multimap::BidirectionalMultimap, janus_rtp::AudioLevel};
 use crate::{conf::SpeakingNotifications, janus_callbacks};
 
@@ -24,6 +29,8 @@ use crate::{conf::SpeakingNotifications, janus_callbacks};
 pub type StreamId = Uuid;
 pub type AgentId = String;
 pub type Session = Box<Arc<SessionWrapper<SessionId>>>;
+/// The publisher of a removed stream and its subscribers.
+pub type RemovedStream = (SessionId, Option<Vec<SessionId>>);
 
 ///////////////////////////////////////////////////////////////////////////////
 
@@ -34,6 +41,11 @@ impl SessionId {
     pub fn new(id: u64) -> Self {
         Self(id)
     }
+
+    /// Distributes sessions among `shards` evenly since Janus handle ids are random.
+    pub fn shard(&self, shards: usize) -> usize {
+        (self.0 % shards.max(1) as u64) as usize
+    }
 }
 
 impl fmt::Display for SessionId {
@@ -55,8 +67,10 @@ pub struct SessionState {
     last_remb_timestamp: AtomicI64,
     last_fir_timestamp: AtomicI64,
     last_rtp_packet_timestamp: AtomicI64,
-    recorder: Option<RecorderHandle>,
-    audio_level_ext_id: Option<u32>,
+    last_rtcp_packet_timestamp: AtomicI64,
+    recorder: ArcSwapOption<RecorderHandle>,
+    /// Zero stands for no extension since it's not a valid extension id.
+    audio_level_ext_id: AtomicU32,
 }
 
 impl SessionState {
@@ -67,12 +81,13 @@ impl SessionState {
             initial_rembs_counter: AtomicU64::new(0),
             last_remb_timestamp: AtomicI64::new(0),
             last_rtp_packet_timestamp: AtomicI64::new(0),
-            recorder: None,
+            last_rtcp_packet_timestamp: AtomicI64::new(0),
+            recorder: ArcSwapOption::empty(),
             last_fir_timestamp: AtomicI64::new(0),
             is_speaking: AtomicBool::new(false),
             packets_count: AtomicUsize::new(0),
             audio_level_sum: AtomicUsize::new(0),
-            audio_level_ext_id: None,
+            audio_level_ext_id: AtomicU32::new(0),
         }
     }
 
@@ -145,7 +160,15 @@ impl SessionState {
     }
 
     fn since_last_rtp_packet_timestamp(&self) -> Option<chrono::Duration> {
-        match self.last_rtp_packet_timestamp.load(Ordering::Relaxed) {
+        Self::since_timestamp(&self.last_rtp_packet_timestamp)
+    }
+
+    fn since_last_rtcp_packet_timestamp(&self) -> Option<chrono::Duration> {
+        Self::since_timestamp(&self.last_rtcp_packet_timestamp)
+    }
+
+    fn since_timestamp(timestamp: &AtomicI64) -> Option<chrono::Duration> {
+        match timestamp.load(Ordering::Relaxed) {
             0 => None,
             timestamp => {
                 let naive_dt = NaiveDateTime::from_timestamp(timestamp, 0);
@@ -160,32 +183,36 @@ impl SessionState {
             .store(Utc::now().timestamp(), Ordering::Relaxed);
     }
 
-    pub fn recorder(&self) -> Option<&RecorderHandle> {
-        self.recorder.as_ref()
+    pub fn touch_last_rtcp_packet_timestamp(&self) {
+        self.last_rtcp_packet_timestamp
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
     }
 
-    pub fn recorder_mut(&mut self) -> Option<&mut RecorderHandle> {
-        self.recorder.as_mut()
+    pub fn recorder(&self) -> Option<Arc<RecorderHandle>> {
+        self.recorder.load_full()
     }
 
-    pub fn set_recorder(&mut self, recorder: RecorderHandle) -> &mut Self {
-        self.recorder = Some(recorder);
+    pub fn set_recorder(&self, recorder: RecorderHandle) -> &Self {
+        self.recorder.store(Some(Arc::new(recorder)));
         self
     }
 
-    fn unset_recorder(&mut self) -> &mut Self {
-        self.recorder = None;
-        self
+    fn take_recorder(&self) -> Option<Arc<RecorderHandle>> {
+        self.recorder.swap(None)
     }
 
     /// Set the session state's audio level ext id.
-    pub fn set_audio_level_ext_id(&mut self, audio_level_ext_id: Option<u32>) {
-        self.audio_level_ext_id = audio_level_ext_id;
+    pub fn set_audio_level_ext_id(&self, audio_level_ext_id: Option<u32>) {
+        self.audio_level_ext_id
+            .store(audio_level_ext_id.unwrap_or(0), Ordering::Relaxed);
     }
 
-    /// Get a reference to the session state's audio level ext id.
+    /// Get the session state's audio level ext id.
     pub fn audio_level_ext_id(&self) -> Option<u32> {
-        self.audio_level_ext_id
+        match self.audio_level_ext_id.load(Ordering::Relaxed) {
+            0 => None,
+            id => Some(id),
+        }
     }
 }
 
@@ -214,7 +241,7 @@ impl ReaderConfig {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WriterConfig {
     send_video: bool,
     send_audio: bool,
@@ -266,6 +293,18 @@ impl Default for WriterConfig {
     }
 }
 
+#[cfg(test)]
+impl WriterConfig {
+    /// Makes a config without taking the default bitrate from the app config.
+    pub fn with_video_remb(video_remb: u32) -> Self {
+        Self {
+            send_video: true,
+            send_audio: true,
+            video_remb,
+        }
+    }
+}
+
 static DEFAULT_WRITER_CONFIG: Lazy<WriterConfig> = Lazy::new(Default::default);
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -282,22 +321,197 @@ impl UnusedSession {
     }
 }
 
-pub enum JoinStreamError {
-    StreamNotFound,
-    SessionNotFound,
+#[derive(Debug)]
+pub enum SwitchboardError {
+    StreamNotFound(StreamId),
+    SessionNotFound(SessionId),
+    /// The server hosts as many agents as it's allowed to.
     TooManyAgents,
+    /// The session is already a publisher or a reader.
+    WrongRole(SessionId),
+    /// The agent has as many sessions as `max_sessions_per_agent`.
+    TooManySessions(AgentId),
+    /// The stream has as many readers as its `max_readers`.
+    StreamFull(StreamId),
+    /// The stream's free seats are reserved for other agents.
+    SeatsReserved(StreamId),
+    /// The stream belongs to another agent or may not be taken over.
+    TakeoverDenied {
+        stream_id: StreamId,
+        owner: AgentId,
+    },
+    LockPoisoned,
+    Recorder(anyhow::Error),
+}
+
+impl SwitchboardError {
+    /// Stable error type and title to respond with.
+    pub fn kind(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::StreamNotFound(_) => ("stream_not_found", "Stream not found"),
+            Self::SessionNotFound(_) => ("session_not_found", "Session not found"),
+            Self::TooManyAgents => ("capacity_exceeded", "Too many agents on server"),
+            Self::WrongRole(_) => ("wrong_session_role", "Session has another role"),
+            Self::TooManySessions(_) => ("too_many_sessions", "Too many sessions of agent"),
+            Self::StreamFull(_) => ("stream_full", "Stream has no free seats"),
+            Self::SeatsReserved(_) => ("stream_seats_reserved", "Stream seats are reserved"),
+            Self::TakeoverDenied { .. } => ("stream_takeover_denied", "Stream takeover denied"),
+            Self::LockPoisoned => ("switchboard_unavailable", "Switchboard is unavailable"),
+            Self::Recorder(_) => ("recorder_error", "Recorder error"),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::StreamNotFound(_) | Self::SessionNotFound(_) => StatusCode::NOT_FOUND,
+            Self::TooManyAgents | Self::StreamFull(_) | Self::SeatsReserved(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::WrongRole(_) | Self::TooManySessions(_) => StatusCode::CONFLICT,
+            Self::TakeoverDenied { .. } => StatusCode::FORBIDDEN,
+            Self::LockPoisoned | Self::Recorder(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for SwitchboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::StreamNotFound(id) => write!(f, "Stream {} does not exist", id),
+            Self::SessionNotFound(id) => write!(f, "Session not found for id = {}", id),
+            Self::TooManyAgents => write!(f, "Too many agents on server"),
+            Self::WrongRole(id) => write!(f, "Session {} is already in use", id),
+            Self::TooManySessions(agent_id) => {
+                write!(f, "Agent {} has too many sessions", agent_id)
+            }
+            Self::StreamFull(id) => write!(f, "Stream {} has no free seats", id),
+            Self::SeatsReserved(id) => write!(f, "Free seats of stream {} are reserved", id),
+            Self::TakeoverDenied { stream_id, owner } => write!(
+                f,
+                "Stream {} is owned by {} and may not be taken over",
+                stream_id, owner
+            ),
+            Self::LockPoisoned => write!(f, "Failed to acquire switchboard lock"),
+            Self::Recorder(err) => write!(f, "Recorder error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SwitchboardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Recorder(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A stream which publisher is lost and whose readers wait for it to come back.
+#[derive(Debug)]
+struct PausedStream {
+    since: Instant,
+    readers: Vec<SessionId>,
+}
+
+/// A publisher being replaced which is relayed to readers until the new one sends a keyframe.
+#[derive(Debug)]
+struct Takeover {
+    old_publisher: SessionId,
+    since: Instant,
+    /// What the stream is given back to the previous publisher with if the new one leaves.
+    old_agent_id: Option<AgentId>,
+    old_takeover_policy: Option<TakeoverPolicy>,
+    old_writer_config: Option<WriterConfig>,
+    old_reader_limits: Option<ReaderLimits>,
+}
+
+/// Limits the number of agents reading a stream.
+#[derive(Debug, Clone)]
+pub struct ReaderLimits {
+    max_readers: usize,
+    /// Privileged agents which always have a seat kept for them.
+    reserved_for: Vec<AgentId>,
+}
+
+impl ReaderLimits {
+    pub fn new(max_readers: usize, reserved_for: Vec<AgentId>) -> Self {
+        Self {
+            max_readers,
+            reserved_for,
+        }
+    }
+}
+
+/// Who may create a stream which already has an owner replacing its publisher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TakeoverPolicy {
+    /// Only the agent which has created the stream.
+    SameAgentOnly,
+    Allow,
+    /// Nobody while the publisher is there. The owner may still resume a paused stream.
+    Deny,
+}
+
+impl Default for TakeoverPolicy {
+    fn default() -> Self {
+        Self::Allow
+    }
+}
+
+/// The state of a stream readers are notified about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamState {
+    Paused,
+    Resumed,
+}
+
+/// What should be pushed to a session once the switchboard lock is released.
+#[derive(Debug)]
+enum Notification {
+    StreamState(SessionId, StreamId, StreamState),
+    /// The session gets finished after the agent is notified.
+    SessionEvicted(SessionId, AgentId),
+}
+
+/// What to do when an agent has more sessions than `max_sessions_per_agent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionsPolicy {
+    EvictOldest,
+    RejectNewest,
+    /// Evict the oldest sessions of the agent reading the same stream only.
+    PerStream,
+}
+
+impl Default for SessionsPolicy {
+    fn default() -> Self {
+        Self::EvictOldest
+    }
 }
 
 #[derive(Debug)]
 pub struct Switchboard {
     unused_sessions: FnvHashMap<SessionId, UnusedSession>,
     sessions: FnvHashMap<SessionId, Session>,
-    states: FnvHashMap<SessionId, SessionState>,
+    states: FnvHashMap<SessionId, Arc<SessionState>>,
     agents: BidirectionalMultimap<AgentId, SessionId>,
     publishers: FnvHashMap<StreamId, SessionId>,
     publishers_subscribers: BidirectionalMultimap<SessionId, SessionId>,
     reader_configs: FnvHashMap<AgentId, FnvHashMap<StreamId, ReaderConfig>>,
     writer_configs: FnvHashMap<StreamId, WriterConfig>,
+    paused_streams: FnvHashMap<StreamId, PausedStream>,
+    takeovers: FnvHashMap<StreamId, Takeover>,
+    /// Agents which have created live or paused streams.
+    owners: FnvHashMap<StreamId, AgentId>,
+    /// Takeover policies the owners have set for their streams overriding the config.
+    takeover_policies: FnvHashMap<StreamId, TakeoverPolicy>,
+    reader_limits: FnvHashMap<StreamId, ReaderLimits>,
+    /// Notifications to push once the lock is released since pushing takes a read lock.
+    notifications: Vec<Notification>,
+    /// Sessions which relay table entries have changed since it was published.
+    relay_changes: FnvHashSet<SessionId>,
     cfg: SwitchboardConfig,
 }
 
@@ -312,6 +526,13 @@ impl Switchboard {
             reader_configs: FnvHashMap::default(),
             writer_configs: FnvHashMap::default(),
             unused_sessions: FnvHashMap::default(),
+            paused_streams: FnvHashMap::default(),
+            takeovers: FnvHashMap::default(),
+            owners: FnvHashMap::default(),
+            takeover_policies: FnvHashMap::default(),
+            reader_limits: FnvHashMap::default(),
+            notifications: Vec::new(),
+            relay_changes: FnvHashSet::default(),
             cfg,
         }
     }
@@ -366,7 +587,7 @@ impl Switchboard {
         }
     }
 
-    pub fn disconnect(&self, id: SessionId) -> Result<()> {
+    pub fn disconnect(&self, id: SessionId) -> Result<(), SwitchboardError> {
         info!("Disconnecting session asynchronously"; {"handle_id": id});
 
         let session = self.session(id)?;
@@ -375,14 +596,40 @@ impl Switchboard {
         Ok(())
     }
 
-    pub fn handle_disconnect(&mut self, id: SessionId) -> Result<()> {
+    pub fn handle_disconnect(&mut self, id: SessionId) -> Result<(), SwitchboardError> {
         info!(
             "Session is about to disconnect. Removing it from the switchboard.";
             {"handle_id": id}
         );
 
-        for subscriber in self.subscribers_to(id).iter().copied() {
-            self.disconnect(subscriber)?;
+        self.relay_changes.insert(id);
+
+        // The reader leaves the publisher's forwarding table.
+        if let Some(publisher) = self.publisher_to(id) {
+            self.relay_changes.insert(publisher);
+        }
+
+        // End the takeover right away when either side of it leaves.
+        let takeovers: Vec<(StreamId, bool)> = self
+            .takeovers
+            .iter()
+            .filter_map(|(stream_id, takeover)| {
+                if takeover.old_publisher == id {
+                    Some((*stream_id, true))
+                } else if self.publisher_of(*stream_id) == Some(id) {
+                    Some((*stream_id, false))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for (stream_id, is_old_publisher) in takeovers {
+            if is_old_publisher {
+                self.finish_takeover(stream_id);
+            } else {
+                self.cancel_takeover(stream_id)?;
+            }
         }
 
         let stream_ids: Vec<StreamId> = self
@@ -392,10 +639,23 @@ impl Switchboard {
             .map(|(stream_id, _)| stream_id.to_owned())
             .collect();
 
+        // Readers of a lost publisher stay attached for a while to let it reconnect.
+        let should_pause = !stream_ids.is_empty() && self.publisher_grace_period().is_some();
+
+        if !should_pause {
+            for subscriber in self.subscribers_to(id).iter().copied() {
+                self.disconnect(subscriber)?;
+            }
+        }
+
+        for paused in self.paused_streams.values_mut() {
+            paused.readers.retain(|reader| *reader != id);
+        }
+
         if let Some(agent) = self.agents.remove_value(&id) {
             if !stream_ids.is_empty() {
                 // We're publisher so remove everything.
-                self.reader_configs.remove(&agent);
+                self.remove_reader_configs(&agent);
             } else {
                 // We're subscriber so need to check if we have
                 // multiple reader configs (case with subgroups in minigroup).
@@ -414,13 +674,17 @@ impl Switchboard {
                 };
 
                 if should_remove {
-                    self.reader_configs.remove(&agent);
+                    self.remove_reader_configs(&agent);
                 }
             }
         }
 
         for stream_id in stream_ids {
-            self.remove_stream(stream_id)?;
+            if should_pause {
+                self.pause_stream(stream_id)?;
+            } else {
+                self.remove_stream(stream_id)?;
+            }
         }
         self.unused_sessions.remove(&id);
         self.sessions.remove(&id);
@@ -430,29 +694,41 @@ impl Switchboard {
         Ok(())
     }
 
-    pub fn session(&self, id: SessionId) -> Result<&Session> {
+    fn remove_reader_configs(&mut self, agent: &AgentId) {
+        if let Some(configs) = self.reader_configs.remove(agent) {
+            for stream_id in configs.keys() {
+                self.stream_relay_changed(*stream_id);
+            }
+        }
+    }
+
+    pub fn session(&self, id: SessionId) -> Result<&Session, SwitchboardError> {
         self.sessions
             .get(&id)
-            .ok_or_else(|| format_err!("Session not found for id = {}", id))
+            .ok_or(SwitchboardError::SessionNotFound(id))
     }
 
-    pub fn lookup_unused_session(&self, id: SessionId) -> Result<&Session> {
+    pub fn lookup_unused_session(&self, id: SessionId) -> Result<&Session, SwitchboardError> {
         self.unused_sessions
             .get(&id)
             .map(|us| &us.session)
-            .ok_or_else(|| format_err!("Session not found for id = {}", id))
+            .ok_or(SwitchboardError::SessionNotFound(id))
     }
 
-    pub fn state(&self, id: SessionId) -> Result<&SessionState> {
+    pub fn state(&self, id: SessionId) -> Result<&SessionState, SwitchboardError> {
         self.states
             .get(&id)
-            .ok_or_else(|| format_err!("Session state not found for id = {}", id))
+            .map(|state| state.as_ref())
+            .ok_or(SwitchboardError::SessionNotFound(id))
     }
 
-    pub fn state_mut(&mut self, id: SessionId) -> Result<&mut SessionState> {
-        self.states
-            .get_mut(&id)
-            .ok_or_else(|| format_err!("Session state not found for id = {}", id))
+    /// Takes the new session out of unused ones to give it a role.
+    fn take_unused_session(&mut self, id: SessionId) -> Result<UnusedSession, SwitchboardError> {
+        match self.unused_sessions.remove(&id) {
+            Some(session) => Ok(session),
+            None if self.sessions.contains_key(&id) => Err(SwitchboardError::WrongRole(id)),
+            None => Err(SwitchboardError::SessionNotFound(id)),
+        }
     }
 
     pub fn agent_sessions(&self, id: &AgentId) -> &[SessionId] {
@@ -491,6 +767,22 @@ impl Switchboard {
         })
     }
 
+    /// Returns the recorder of the stream if it's being recorded.
+    pub fn stream_recorder(&self, stream_id: StreamId) -> Option<Arc<RecorderHandle>> {
+        self.publisher_of(stream_id)
+            .and_then(|publisher| self.state(publisher).ok())
+            .and_then(|state| state.recorder())
+    }
+
+    /// Logs the event to the stream's record if it's being recorded.
+    pub fn record_event(&self, stream_id: StreamId, event: StreamEvent) {
+        if let Some(recorder) = self.stream_recorder(stream_id) {
+            if let Err(err) = recorder.record_event(event) {
+                err!("Failed to record event: {:?}", err; {"rtc_id": stream_id});
+            }
+        }
+    }
+
     pub fn reader_config(
         &self,
         stream_id: StreamId,
@@ -507,6 +799,7 @@ impl Switchboard {
         reader_id: &AgentId,
         config: ReaderConfig,
     ) {
+        self.stream_relay_changed(stream_id);
         self.reader_configs
             .entry(reader_id.to_owned())
             .or_default()
@@ -516,7 +809,7 @@ impl Switchboard {
     pub fn writer_config(&self, stream_id: StreamId) -> &WriterConfig {
         self.writer_configs
             .get(&stream_id)
-            .unwrap_or(&DEFAULT_WRITER_CONFIG)
+            .unwrap_or_else(|| &DEFAULT_WRITER_CONFIG)
     }
 
     pub fn set_writer_config(
@@ -525,6 +818,7 @@ impl Switchboard {
         writer_config: WriterConfig,
     ) -> Option<WriterConfig> {
         info!("SET WRITER CONFIG: {:?}", writer_config; {"rtc_id": stream_id});
+        self.stream_relay_changed(stream_id);
         self.writer_configs.insert(stream_id, writer_config)
     }
 
@@ -533,66 +827,213 @@ impl Switchboard {
         id: StreamId,
         publisher: SessionId,
         agent_id: AgentId,
-    ) -> Result<()> {
+        takeover_policy: Option<TakeoverPolicy>,
+    ) -> Result<(), SwitchboardError> {
         info!("Creating stream"; {"rtc_id": id, "handle_id": publisher, "agent_id": agent_id});
-        let session = self.unused_sessions.remove(&publisher).ok_or_else(|| {
-            anyhow!(
-                "Publisher's session id: {} not present in the new_sessions set",
-                publisher
-            )
-        })?;
+        self.check_takeover(id, &agent_id)?;
+        let session = self.take_unused_session(publisher)?;
+
+        self.relay_changes.insert(publisher);
+
+        // The owner's policy stays until the owner changes it while a new owner starts over.
+        let old_takeover_policy = self.takeover_policies.get(&id).copied();
+        let is_same_owner = self.owner_of(id) == Some(&agent_id);
+
+        let takeover_policy = match takeover_policy {
+            Some(takeover_policy) => Some(takeover_policy),
+            None if is_same_owner => old_takeover_policy,
+            None => None,
+        };
+
+        let old_agent_id = self
+            .publisher_of(id)
+            .and_then(|old_publisher| self.agent_id(old_publisher))
+            .cloned();
+
+        if self.publishers.contains_key(&id) {
+            let event = StreamEvent::PublisherTakeover {
+                previous_agent_id: old_agent_id.clone(),
+                agent_id: agent_id.clone(),
+            };
+
+            self.record_event(id, event);
+        }
+
+        let old_writer_config = self.writer_configs.get(&id).cloned();
+        let old_reader_limits = self.reader_limits.get(&id).cloned();
         let old = self.remove_stream(id)?;
         self.sessions.insert(publisher, session.session);
-        self.states.insert(publisher, SessionState::new());
+        self.states.insert(publisher, Arc::new(SessionState::new()));
         self.publishers.insert(id, publisher);
+
+        if let Some(paused) = self.paused_streams.remove(&id) {
+            info!(
+                "Publisher is back in {:?}; resuming stream", paused.since.elapsed();
+                {"rtc_id": id, "handle_id": publisher}
+            );
+
+            for reader in paused.readers {
+                self.publishers_subscribers.associate(publisher, reader);
+                self.relay_changes.insert(reader);
+                self.notifications.push(Notification::StreamState(
+                    reader,
+                    id,
+                    StreamState::Resumed,
+                ));
+            }
+        }
         if let Some((old_publisher, subscribers)) = old {
-            info!("Old publisher {} for stream {} removed", old_publisher, id);
-            for subscriber in subscribers.into_iter().flatten() {
-                self.publishers_subscribers.associate(publisher, subscriber);
+            let subscribers = subscribers.unwrap_or_default();
+
+            if self.cfg.takeover_keyframe_timeout.is_zero() || subscribers.is_empty() {
+                info!("Old publisher {} for stream {} removed", old_publisher, id);
+                for subscriber in subscribers {
+                    self.publishers_subscribers.associate(publisher, subscriber);
+                    self.relay_changes.insert(subscriber);
+                }
+                self.disconnect(old_publisher)?;
+            } else {
+                info!(
+                    "Relaying old publisher {} until the new one sends a keyframe", old_publisher;
+                    {"rtc_id": id, "handle_id": publisher}
+                );
+
+                for subscriber in subscribers {
+                    self.publishers_subscribers
+                        .associate(old_publisher, subscriber);
+                    self.relay_changes.insert(subscriber);
+                }
+
+                self.relay_changes.insert(old_publisher);
+
+                let takeover = Takeover {
+                    old_publisher,
+                    since: Instant::now(),
+                    old_agent_id,
+                    old_takeover_policy,
+                    old_writer_config,
+                    old_reader_limits,
+                };
+
+                self.takeovers.insert(id, takeover);
             }
-            self.disconnect(old_publisher)?;
         }
 
+        match takeover_policy {
+            Some(takeover_policy) => self.takeover_policies.insert(id, takeover_policy),
+            None => self.takeover_policies.remove(&id),
+        };
+
+        self.owners.insert(id, agent_id.clone());
         self.agents.associate(agent_id, publisher);
         Ok(())
     }
 
+    pub fn owner_of(&self, stream_id: StreamId) -> Option<&AgentId> {
+        self.owners.get(&stream_id)
+    }
+
+    /// Checks whether the agent may create the stream according to the takeover policy
+    /// set by the owner or the configured one.
+    fn check_takeover(&self, id: StreamId, agent_id: &AgentId) -> Result<(), SwitchboardError> {
+        let owner = match self.owner_of(id) {
+            Some(owner) => owner,
+            None => return Ok(()),
+        };
+
+        let policy = self
+            .takeover_policies
+            .get(&id)
+            .copied()
+            .unwrap_or(self.cfg.takeover_policy);
+
+        let is_permitted = match policy {
+            TakeoverPolicy::Allow => true,
+            TakeoverPolicy::SameAgentOnly => owner == agent_id,
+            TakeoverPolicy::Deny => owner == agent_id && self.paused_streams.contains_key(&id),
+        };
+
+        if is_permitted {
+            Ok(())
+        } else {
+            warn!(
+                "Stream takeover denied; owned by {}", owner;
+                {"rtc_id": id, "agent_id": agent_id}
+            );
+
+            Err(SwitchboardError::TakeoverDenied {
+                stream_id: id,
+                owner: owner.to_owned(),
+            })
+        }
+    }
+
     pub fn join_stream(
         &mut self,
         id: StreamId,
         subscriber: SessionId,
         agent_id: AgentId,
-    ) -> Result<(), JoinStreamError> {
-        let publisher = self
-            .publishers
-            .get(&id)
-            .map(|p| p.to_owned())
-            .ok_or(JoinStreamError::StreamNotFound)?;
+    ) -> Result<(), SwitchboardError> {
+        let publisher = self.publishers.get(&id).map(|p| p.to_owned());
+
+        if publisher.is_none() && !self.paused_streams.contains_key(&id) {
+            return Err(SwitchboardError::StreamNotFound(id));
+        }
 
         if let Some(max_agents) = self.cfg.max_agents {
             if self.agents_count() >= max_agents {
-                return Err(JoinStreamError::TooManyAgents);
+                return Err(SwitchboardError::TooManyAgents);
             }
         }
 
-        let session = self
-            .unused_sessions
-            .remove(&subscriber)
-            .ok_or(JoinStreamError::SessionNotFound)?;
+        let max_sessions_per_agent = self.cfg.max_sessions_per_agent.max(1);
+
+        if self.cfg.sessions_policy == SessionsPolicy::RejectNewest
+            && self.agent_sessions(&agent_id).len() >= max_sessions_per_agent
+        {
+            return Err(SwitchboardError::TooManySessions(agent_id));
+        }
+
+        self.check_reader_limits(id, &agent_id)?;
+        let session = self.take_unused_session(subscriber)?;
 
         self.sessions.insert(subscriber, session.session);
-        self.states.insert(subscriber, SessionState::new());
+
+        // Start counting from joining so readers which never send RTCP get vacuumed too.
+        let state = SessionState::new();
+        state.touch_last_rtcp_packet_timestamp();
+        self.states.insert(subscriber, Arc::new(state));
 
         verb!(
             "Joining to stream";
             {"rtc_id": id, "handle_id": subscriber, "agent_id": agent_id}
         );
 
-        self.publishers_subscribers.associate(publisher, subscriber);
+        match publisher {
+            Some(publisher) => self.publishers_subscribers.associate(publisher, subscriber),
+            None => {
+                if let Some(paused) = self.paused_streams.get_mut(&id) {
+                    paused.readers.push(subscriber);
+                }
+
+                self.notifications.push(Notification::StreamState(
+                    subscriber,
+                    id,
+                    StreamState::Paused,
+                ));
+            }
+        }
+
         self.agents.associate(agent_id.clone(), subscriber);
+        self.relay_changes.insert(subscriber);
+        self.relay_changes.extend(publisher);
 
-        let max_sessions_per_agent = self.cfg.max_sessions_per_agent.max(1);
-        let agent_sessions = self.agent_sessions(&agent_id);
+        let mut agent_sessions = self.agent_sessions(&agent_id).to_vec();
+
+        if self.cfg.sessions_policy == SessionsPolicy::PerStream {
+            let readers = self.stream_readers(id);
+            agent_sessions.retain(|s| readers.contains(s));
+        }
 
         let remove_sessions_count = if agent_sessions.len() > max_sessions_per_agent {
             agent_sessions.len() - max_sessions_per_agent
@@ -600,21 +1041,82 @@ impl Switchboard {
             0
         };
         let sessions_to_remove = agent_sessions
-            .iter()
-            .filter(|s| **s != subscriber)
+            .into_iter()
+            .filter(|s| *s != subscriber)
             .take(remove_sessions_count);
 
         for s_id in sessions_to_remove {
-            let session = self
-                .session(*s_id)
-                .map_err(|_err| JoinStreamError::SessionNotFound)?;
-
             info!(
                 "There are more sessions than allowed; finishing session";
                 {"agent_id": agent_id, "session_id": s_id}
             );
 
-            janus_callbacks::end_session(session);
+            self.notifications
+                .push(Notification::SessionEvicted(s_id, agent_id.clone()));
+        }
+
+        Ok(())
+    }
+
+    pub fn set_reader_limits(&mut self, id: StreamId, limits: ReaderLimits) {
+        self.reader_limits.insert(id, limits);
+    }
+
+    /// Returns readers of the stream including those waiting for its publisher.
+    fn stream_readers(&self, id: StreamId) -> Vec<SessionId> {
+        let mut readers = Vec::new();
+
+        if let Some(publisher) = self.publisher_of(id) {
+            readers.extend_from_slice(self.subscribers_to(publisher));
+        }
+
+        if let Some(takeover) = self.takeovers.get(&id) {
+            readers.extend_from_slice(self.subscribers_to(takeover.old_publisher));
+        }
+
+        if let Some(paused) = self.paused_streams.get(&id) {
+            readers.extend_from_slice(&paused.readers);
+        }
+
+        readers
+    }
+
+    /// Checks whether the agent may take a seat of the stream.
+    fn check_reader_limits(
+        &self,
+        id: StreamId,
+        agent_id: &AgentId,
+    ) -> Result<(), SwitchboardError> {
+        let limits = match self.reader_limits.get(&id) {
+            Some(limits) => limits,
+            None => return Ok(()),
+        };
+
+        let readers: FnvHashSet<&AgentId> = self
+            .stream_readers(id)
+            .into_iter()
+            .filter_map(|reader| self.agent_id(reader))
+            .collect();
+
+        // A reconnecting agent keeps its seat.
+        if readers.contains(agent_id) {
+            return Ok(());
+        }
+
+        if readers.len() >= limits.max_readers {
+            return Err(SwitchboardError::StreamFull(id));
+        }
+
+        if !limits.reserved_for.contains(agent_id) {
+            let vacant_reserved_seats = limits
+                .reserved_for
+                .iter()
+                .filter(|agent_id| !readers.contains(agent_id))
+                .count();
+
+            if readers.len() + vacant_reserved_seats >= limits.max_readers {
+                return Err(SwitchboardError::SeatsReserved(id));
+            }
         }
 
         Ok(())
@@ -623,36 +1125,332 @@ impl Switchboard {
     pub fn remove_stream(
         &mut self,
         id: StreamId,
-    ) -> Result<Option<(SessionId, Option<Vec<SessionId>>)>> {
+    ) -> Result<Option<RemovedStream>, SwitchboardError> {
         info!("Removing stream"; {"rtc_id": id});
+        self.complete_takeover(id)?;
+
         if let Some(publisher) = self.publishers.remove(&id) {
+            self.relay_changes.insert(publisher);
             self.stop_recording(publisher)?;
             self.writer_configs.remove(&id);
+            self.owners.remove(&id);
+            self.takeover_policies.remove(&id);
+            self.reader_limits.remove(&id);
             self.agents.remove_value(&publisher);
-            Ok(Some((
-                publisher,
-                self.publishers_subscribers.remove_key(&publisher),
-            )))
+            let readers = self.publishers_subscribers.remove_key(&publisher);
+            self.relay_changes.extend(readers.iter().flatten().copied());
+            Ok(Some((publisher, readers)))
         } else {
             Ok(None)
         }
     }
 
-    fn stop_recording(&mut self, publisher: SessionId) -> Result<()> {
-        let state = self.state_mut(publisher)?;
+    /// Moves readers of the previous publisher to the current one ending the takeover.
+    /// Returns the previous publisher which is left to disconnect.
+    fn finish_takeover(&mut self, id: StreamId) -> Option<SessionId> {
+        let takeover = self.takeovers.remove(&id)?;
+        self.relay_changes.insert(takeover.old_publisher);
+
+        let readers = self
+            .publishers_subscribers
+            .remove_key(&takeover.old_publisher)
+            .unwrap_or_default();
+
+        self.relay_changes.extend(readers.iter().copied());
+
+        if let Some(publisher) = self.publisher_of(id) {
+            self.relay_changes.insert(publisher);
+
+            for reader in readers {
+                self.publishers_subscribers.associate(publisher, reader);
+            }
+        }
+
+        Some(takeover.old_publisher)
+    }
+
+    /// Switches readers to the new publisher and disconnects the previous one.
+    pub fn complete_takeover(&mut self, id: StreamId) -> Result<(), SwitchboardError> {
+        if let Some(old_publisher) = self.finish_takeover(id) {
+            info!("Switching readers to the new publisher"; {"rtc_id": id, "handle_id": old_publisher});
+            self.disconnect(old_publisher)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gives the stream back to the previous publisher when the new one leaves
+    /// before the takeover completes. Readers which have joined meanwhile are moved too.
+    fn cancel_takeover(&mut self, id: StreamId) -> Result<(), SwitchboardError> {
+        let takeover = match self.takeovers.remove(&id) {
+            Some(takeover) => takeover,
+            None => return Ok(()),
+        };
+
+        let old_publisher = takeover.old_publisher;
+        info!("New publisher left; cancelling takeover"; {"rtc_id": id, "handle_id": old_publisher});
+        self.relay_changes.insert(old_publisher);
+
+        if let Some(publisher) = self.publishers.insert(id, old_publisher) {
+            self.relay_changes.insert(publisher);
+            self.stop_recording(publisher)?;
+
+            let readers = self
+                .publishers_subscribers
+                .remove_key(&publisher)
+                .unwrap_or_default();
+
+            for reader in readers {
+                self.publishers_subscribers.associate(old_publisher, reader);
+                self.relay_changes.insert(reader);
+            }
+        }
+
+        if let Some(agent_id) = takeover.old_agent_id {
+            self.owners.insert(id, agent_id.clone());
+            self.agents.associate(agent_id, old_publisher);
+        }
+
+        match takeover.old_takeover_policy {
+            Some(takeover_policy) => self.takeover_policies.insert(id, takeover_policy),
+            None => self.takeover_policies.remove(&id),
+        };
+
+        match takeover.old_writer_config {
+            Some(writer_config) => self.writer_configs.insert(id, writer_config),
+            None => self.writer_configs.remove(&id),
+        };
+
+        match takeover.old_reader_limits {
+            Some(limits) => self.reader_limits.insert(id, limits),
+            None => self.reader_limits.remove(&id),
+        };
+
+        Ok(())
+    }
+
+    /// Switches readers of takeovers which new publishers haven't sent a keyframe in time.
+    pub fn vacuum_takeovers(&mut self) {
+        let timeout = self.cfg.takeover_keyframe_timeout;
+
+        let expired: Vec<StreamId> = self
+            .takeovers
+            .iter()
+            .filter(|(_, takeover)| takeover.since.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            warn!("No keyframe from the new publisher in time; switching readers anyway"; {"rtc_id": id});
+
+            if let Err(err) = self.complete_takeover(id) {
+                err!("Failed to complete takeover: {}", err; {"rtc_id": id});
+            }
+        }
+    }
+
+    /// Removes the stream of a lost publisher keeping its readers for the grace period.
+    fn pause_stream(&mut self, id: StreamId) -> Result<(), SwitchboardError> {
+        // Only the owner is expected to come back and readers keep on joining meanwhile.
+        let owner = self.owners.get(&id).cloned();
+        let takeover_policy = self.takeover_policies.get(&id).copied();
+        let reader_limits = self.reader_limits.get(&id).cloned();
+
+        let readers = match self.remove_stream(id)? {
+            Some((_publisher, readers)) => readers.unwrap_or_default(),
+            None => return Ok(()),
+        };
+
+        if let Some(owner) = owner {
+            self.owners.insert(id, owner);
+        }
+
+        if let Some(takeover_policy) = takeover_policy {
+            self.takeover_policies.insert(id, takeover_policy);
+        }
+
+        if let Some(reader_limits) = reader_limits {
+            self.reader_limits.insert(id, reader_limits);
+        }
+
+        info!("Publisher lost; pausing stream for {} readers", readers.len(); {"rtc_id": id});
 
-        if let Some(recorder) = state.recorder_mut() {
+        for reader in readers.iter().copied() {
+            self.notifications
+                .push(Notification::StreamState(reader, id, StreamState::Paused));
+        }
+
+        self.paused_streams.insert(
+            id,
+            PausedStream {
+                since: Instant::now(),
+                readers,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Forgets a paused stream returning the readers still waiting for it.
+    pub fn remove_paused_stream(&mut self, id: StreamId) -> Vec<SessionId> {
+        if !self.publishers.contains_key(&id) {
+            self.owners.remove(&id);
+            self.takeover_policies.remove(&id);
+            self.reader_limits.remove(&id);
+        }
+
+        self.paused_streams
+            .remove(&id)
+            .map(|paused| paused.readers)
+            .unwrap_or_default()
+    }
+
+    fn publisher_grace_period(&self) -> Option<Duration> {
+        self.cfg
+            .publisher_grace_period
+            .filter(|grace_period| !grace_period.is_zero())
+    }
+
+    /// Disconnects readers of paused streams which publishers haven't come back in time.
+    pub fn vacuum_paused_streams(&mut self) {
+        let grace_period = match self.publisher_grace_period() {
+            Some(grace_period) => grace_period,
+            None => return,
+        };
+
+        let expired: Vec<StreamId> = self
+            .paused_streams
+            .iter()
+            .filter(|(_, paused)| paused.since.elapsed() >= grace_period)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            let readers = self.remove_paused_stream(id);
+            warn!("Publisher hasn't come back in time; disconnecting readers"; {"rtc_id": id});
+
+            for reader in readers {
+                if let Err(err) = self.disconnect(reader) {
+                    err!("Failed to disconnect reader: {}", err; {"rtc_id": id, "handle_id": reader});
+                }
+            }
+        }
+    }
+
+    fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    fn stop_recording(&mut self, publisher: SessionId) -> Result<(), SwitchboardError> {
+        let state = self.state(publisher)?;
+
+        if let Some(recorder) = state.take_recorder() {
             info!("Stopping recording"; {"handle_id": publisher});
 
-            recorder
-                .stop_recording()
-                .map_err(|err| format_err!("Failed to stop recording {}: {}", publisher, err))?;
+            recorder.stop_recording().map_err(|err| {
+                SwitchboardError::Recorder(format_err!(
+                    "Failed to stop recording {}: {}",
+                    publisher,
+                    err
+                ))
+            })?;
         }
 
-        state.unset_recorder();
         Ok(())
     }
 
+    /// Marks the forwarding table of the stream's publishers outdated.
+    fn stream_relay_changed(&mut self, stream_id: StreamId) {
+        let publisher = self.publisher_of(stream_id);
+        self.relay_changes.extend(publisher);
+
+        if let Some(takeover) = self.takeovers.get(&stream_id) {
+            self.relay_changes.insert(takeover.old_publisher);
+        }
+    }
+
+    /// Returns the sessions which relay table entries are outdated and resets them.
+    fn take_relay_changes(&mut self) -> FnvHashSet<SessionId> {
+        std::mem::take(&mut self.relay_changes)
+    }
+
+    /// Builds an immutable view of the switchboard for the media relay path.
+    /// Only the entries of the changed sessions are rebuilt and the rest are taken
+    /// from the previous version.
+    pub fn relay_table(
+        &self,
+        previous: &RelayTable,
+        changes: &FnvHashSet<SessionId>,
+    ) -> RelayTable {
+        let sessions = previous
+            .sessions
+            .with_changes(changes.iter().map(|id| (*id, self.relay_session(*id))));
+
+        RelayTable { sessions }
+    }
+
+    fn relay_session(&self, id: SessionId) -> Option<RelaySession> {
+        let mut relay_session = RelaySession {
+            session: self.sessions.get(&id)?.clone(),
+            state: self.states.get(&id)?.clone(),
+            agent_id: self.agent_id(id).cloned(),
+            publisher: self.publisher_to(id),
+            forwarding: None,
+            takeover_since: None,
+        };
+
+        // The previous publisher keeps feeding readers until the takeover completes.
+        let old_publisher_of = self
+            .takeovers
+            .iter()
+            .find(|(_, takeover)| takeover.old_publisher == id)
+            .map(|(stream_id, _)| *stream_id);
+
+        if let Some(stream_id) = old_publisher_of {
+            let forwarding = self.forwarding_table(stream_id, id);
+            relay_session.forwarding = Some(Arc::new(forwarding));
+        } else if let Some(stream_id) = self.published_by(id) {
+            let forwarding = self.forwarding_table(stream_id, id);
+            relay_session.forwarding = Some(Arc::new(forwarding));
+            relay_session.takeover_since = self.takeovers.get(&stream_id).map(|t| t.since);
+        }
+
+        Some(relay_session)
+    }
+
+    fn forwarding_table(&self, stream_id: StreamId, publisher: SessionId) -> ForwardingTable {
+        let shards = self.cfg.fan_out_workers.max(1);
+
+        let mut readers = self
+            .subscribers_to(publisher)
+            .iter()
+            .filter_map(|reader| {
+                let reader_config = self.reader_config(stream_id, reader);
+
+                Some(RelayReader {
+                    id: *reader,
+                    session: self.sessions.get(reader)?.clone(),
+                    state: self.states.get(reader)?.clone(),
+                    receive_video: reader_config.map(|c| c.receive_video()).unwrap_or(true),
+                    receive_audio: reader_config.map(|c| c.receive_audio()).unwrap_or(true),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        readers.sort_by_key(|reader| reader.id.shard(shards));
+
+        let shard_ends = (0..shards)
+            .map(|shard| readers.partition_point(|reader| reader.id.shard(shards) <= shard))
+            .collect();
+
+        ForwardingTable {
+            stream_id,
+            writer_config: self.writer_config(stream_id).clone(),
+            readers,
+            shard_ends,
+        }
+    }
+
     pub fn vacuum_sessions(&self, ttl: Duration) -> Result<()> {
         for (_, session) in self.unused_sessions.iter() {
             if session.is_timeouted(ttl) {
@@ -666,11 +1464,15 @@ impl Switchboard {
         for (stream_id, publisher) in self.publishers.iter() {
             match self.vacuum_publisher(*publisher, timeout) {
                 Ok(false) => (),
-                Ok(true) => warn!(
-                    "Publisher timed out; No RTP packets from PeerConnection in {} seconds",
-                    timeout.num_seconds();
-                    {"rtc_id": stream_id, "handle_id": publisher}
-                ),
+                Ok(true) => {
+                    warn!(
+                        "Publisher timed out; No RTP packets from PeerConnection in {} seconds",
+                        timeout.num_seconds();
+                        {"rtc_id": stream_id, "handle_id": publisher}
+                    );
+
+                    Metrics::observe_vacuumed_session(false);
+                }
                 Err(err) => err!(
                     "Failed to vacuum publisher: {}", err;
                     {"rtc_id": stream_id, "handle_id": publisher}
@@ -695,60 +1497,714 @@ impl Switchboard {
 
         Ok(is_timed_out)
     }
+
+    /// Disconnects readers which have stopped sending RTCP, e.g. because the browser is gone.
+    pub fn vacuum_readers(&self, timeout: &chrono::Duration) -> Result<()> {
+        let readers = self
+            .states
+            .iter()
+            .filter(|(id, _)| self.publisher_to(**id).is_some());
+
+        for (reader, state) in readers {
+            let is_timed_out = match state.since_last_rtcp_packet_timestamp() {
+                None => false,
+                Some(duration) => duration >= *timeout,
+            };
+
+            if !is_timed_out {
+                continue;
+            }
+
+            warn!(
+                "Reader timed out; No RTCP packets from PeerConnection in {} seconds",
+                timeout.num_seconds();
+                {"handle_id": reader}
+            );
+
+            match self.disconnect(*reader) {
+                Ok(()) => Metrics::observe_vacuumed_session(true),
+                Err(err) => err!("Failed to vacuum reader: {}", err; {"handle_id": reader}),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 ///////////////////////////////////////////////////////////////////////////////
 
+const RELAY_TABLE_SHARDS: usize = 256;
+
+/// A snapshot of the switchboard the RTP & RTCP packets are relayed with.
+///
+/// It never changes once built: writers publish a new version instead so the relay path
+/// doesn't wait for signalling.
+///
+/// Sessions are split into shards so a new version copies only the shards having changes.
 #[derive(Debug)]
-pub struct LockedSwitchboard(RwLock<Switchboard>);
+pub struct RelayTable {
+    sessions: ShardedMap<SessionId, RelaySession>,
+}
+
+impl Default for RelayTable {
+    fn default() -> Self {
+        Self {
+            sessions: ShardedMap::new(RELAY_TABLE_SHARDS),
+        }
+    }
+}
+
+impl RelayTable {
+    pub fn session(&self, id: SessionId) -> Result<&RelaySession, SwitchboardError> {
+        self.sessions
+            .get(&id)
+            .ok_or(SwitchboardError::SessionNotFound(id))
+    }
+}
+
+#[derive(Debug)]
+pub struct RelaySession {
+    session: Session,
+    state: Arc<SessionState>,
+    agent_id: Option<AgentId>,
+    /// The publisher the session reads from.
+    publisher: Option<SessionId>,
+    /// Where to relay the packets to if the session is a publisher.
+    forwarding: Option<Arc<ForwardingTable>>,
+    /// When the publisher started taking over readers of the stream from the previous one.
+    takeover_since: Option<Instant>,
+}
+
+impl RelaySession {
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    pub fn agent_id(&self) -> Option<&AgentId> {
+        self.agent_id.as_ref()
+    }
+
+    pub fn publisher(&self) -> Option<SessionId> {
+        self.publisher
+    }
+
+    pub fn forwarding(&self) -> Option<&Arc<ForwardingTable>> {
+        self.forwarding.as_ref()
+    }
+
+    pub fn takeover_since(&self) -> Option<Instant> {
+        self.takeover_since
+    }
+}
+
+/// Everything needed to fan out a publisher's packets without further lookups.
+///
+/// Readers are grouped by fan-out worker so each worker gets its share without scanning.
+#[derive(Debug)]
+pub struct ForwardingTable {
+    stream_id: StreamId,
+    writer_config: WriterConfig,
+    readers: Vec<RelayReader>,
+    /// Ends of the workers' shares in `readers`.
+    shard_ends: Vec<usize>,
+}
+
+impl ForwardingTable {
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    pub fn writer_config(&self) -> &WriterConfig {
+        &self.writer_config
+    }
+
+    pub fn readers(&self) -> &[RelayReader] {
+        &self.readers
+    }
+
+    /// Readers to be relayed packets by the fan-out worker.
+    pub fn shard_readers(&self, shard: usize) -> &[RelayReader] {
+        let start = match shard {
+            0 => 0,
+            _ => self.shard_ends.get(shard - 1).copied().unwrap_or(0),
+        };
+
+        let end = self.shard_ends.get(shard).copied().unwrap_or(start);
+        &self.readers[start..end]
+    }
+}
+
+#[derive(Debug)]
+pub struct RelayReader {
+    id: SessionId,
+    session: Session,
+    state: Arc<SessionState>,
+    receive_video: bool,
+    receive_audio: bool,
+}
+
+impl RelayReader {
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    /// Whether the media is not muted by the reader's agent.
+    pub fn receives(&self, is_video: bool) -> bool {
+        match is_video {
+            true => self.receive_video,
+            false => self.receive_audio,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct LockedSwitchboard {
+    switchboard: RwLock<Switchboard>,
+    relay_table: ArcSwap<RelayTable>,
+}
 
 impl LockedSwitchboard {
     pub fn new(cfg: SwitchboardConfig) -> Self {
-        Self(RwLock::new(Switchboard::new(cfg)))
+        Self {
+            switchboard: RwLock::new(Switchboard::new(cfg)),
+            relay_table: ArcSwap::from_pointee(RelayTable::default()),
+        }
     }
 
-    pub fn with_read_lock<F, R>(&self, callback: F) -> Result<R>
+    pub fn with_read_lock<F, R, E>(&self, callback: F) -> Result<R, E>
     where
-        F: FnOnce(RwLockReadGuard<Switchboard>) -> Result<R>,
+        F: FnOnce(RwLockReadGuard<Switchboard>) -> Result<R, E>,
+        E: From<SwitchboardError>,
     {
-        match self.0.read() {
+        match self.switchboard.read() {
             Ok(switchboard) => callback(switchboard),
-            Err(_) => bail!("Failed to acquire switchboard read lock"),
+            Err(_) => Err(SwitchboardError::LockPoisoned.into()),
         }
     }
 
-    pub fn with_write_lock<F, R>(&self, callback: F) -> Result<R>
+    /// Changes the switchboard and publishes a new version of the relay table if needed.
+    pub fn with_write_lock<F, R, E>(&self, callback: F) -> Result<R, E>
     where
-        F: FnOnce(RwLockWriteGuard<Switchboard>) -> Result<R>,
+        F: FnOnce(&mut Switchboard) -> Result<R, E>,
+        E: From<SwitchboardError>,
     {
-        match self.0.write() {
-            Ok(switchboard) => callback(switchboard),
-            Err(_) => bail!("Failed to acquire switchboard write lock"),
+        match self.switchboard.write() {
+            Ok(mut switchboard) => {
+                let result = callback(&mut switchboard);
+
+                // Check even on error since the switchboard may be partially changed.
+                let relay_changes = switchboard.take_relay_changes();
+
+                if !relay_changes.is_empty() {
+                    let relay_table =
+                        switchboard.relay_table(&self.relay_table.load(), &relay_changes);
+                    self.relay_table.store(Arc::new(relay_table));
+                }
+
+                let notifications = switchboard.take_notifications();
+                drop(switchboard);
+
+                for notification in notifications {
+                    self.push_notification(notification);
+                }
+
+                result
+            }
+            Err(_) => Err(SwitchboardError::LockPoisoned.into()),
         }
     }
 
-    pub fn vacuum_publishers_loop(&self, interval: Duration, sessions_ttl: Duration) -> Result<()> {
+    fn push_notification(&self, notification: Notification) {
+        match notification {
+            Notification::StreamState(reader, stream_id, state) => {
+                let result = app!().and_then(|app| {
+                    send_stream_state_notification(&app.janus_sender, reader, stream_id, state)
+                });
+
+                if let Err(err) = result {
+                    warn!(
+                        "Failed to notify reader of the stream state: {:?}", err;
+                        {"rtc_id": stream_id, "handle_id": reader}
+                    );
+                }
+            }
+            Notification::SessionEvicted(session_id, agent_id) => {
+                let result = app!().and_then(|app| {
+                    send_session_evicted_notification(&app.janus_sender, session_id, &agent_id)
+                });
+
+                if let Err(err) = result {
+                    warn!(
+                        "Failed to notify agent of the session eviction: {:?}", err;
+                        {"handle_id": session_id, "agent_id": agent_id}
+                    );
+                }
+
+                self.with_read_lock(|switchboard| switchboard.disconnect(session_id))
+                    .unwrap_or_else(|err| {
+                        warn!("Failed to finish evicted session: {}", err; {"handle_id": session_id})
+                    });
+            }
+        }
+    }
+
+    /// Returns the latest relay table without locking the switchboard.
+    pub fn relay_table(&self) -> Guard<Arc<RelayTable>> {
+        self.relay_table.load()
+    }
+
+    pub fn vacuum_streams_loop(&self, interval: Duration) {
+        info!("Streams vacuum thread spawned");
+        loop {
+            self.with_write_lock(|switchboard| {
+                switchboard.vacuum_paused_streams();
+                switchboard.vacuum_takeovers();
+                Ok::<_, SwitchboardError>(())
+            })
+            .unwrap_or_else(|err| err!("Streams vacuum errored: {}", err));
+
+            thread::sleep(interval);
+        }
+    }
+
+    pub fn vacuum_sessions_loop(&self, config: &General) -> Result<()> {
         info!("Vacuum thread spawned");
+        let publisher_timeout = config.publisher_timeout.unwrap_or(config.vacuum_interval);
+        let publisher_timeout = chrono::Duration::from_std(publisher_timeout)?;
+        let reader_timeout = config
+            .reader_timeout
+            .map(chrono::Duration::from_std)
+            .transpose()?;
+
         loop {
-            self.with_read_lock(|switchboard| {
-                switchboard.vacuum_publishers(&chrono::Duration::from_std(interval)?)?;
-                switchboard.vacuum_sessions(sessions_ttl)?;
+            self.with_read_lock(|switchboard| -> Result<()> {
+                switchboard.vacuum_publishers(&publisher_timeout)?;
+
+                if let Some(reader_timeout) = &reader_timeout {
+                    switchboard.vacuum_readers(reader_timeout)?;
+                }
+
+                switchboard.vacuum_sessions(config.sessions_ttl)?;
                 Ok(())
             })
             .unwrap_or_else(|err| err!("Vacuum errored: {:?}", err));
 
-            thread::sleep(interval);
+            thread::sleep(config.vacuum_interval);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::atomic::Ordering;
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use http::StatusCode;
+    use uuid::Uuid;
+
+    use crate::{
+        conf::{SpeakingNotifications, SwitchboardConfig},
+        janus_rtp::AudioLevel,
+        test_stubs::{is_ended, session},
+    };
+
+    use super::{
+        AgentId, PausedStream, ReaderConfig, ReaderLimits, RelayTable, SessionId, SessionState,
+        SessionsPolicy, StreamId, Switchboard, SwitchboardError, TakeoverPolicy, WriterConfig,
+    };
+
+    fn config() -> SwitchboardConfig {
+        SwitchboardConfig {
+            max_sessions_per_agent: 1,
+            max_agents: None,
+            sessions_policy: SessionsPolicy::EvictOldest,
+            publisher_grace_period: None,
+            takeover_keyframe_timeout: Duration::from_secs(2),
+            takeover_policy: TakeoverPolicy::Allow,
+            fan_out_workers: 0,
+        }
+    }
+
+    /// Creates a stream published by a new session.
+    fn publish(switchboard: &mut Switchboard, stream_id: StreamId, publisher: u64) {
+        let agent_id = format!("web.publisher{}.usr.example.org", publisher);
+        switchboard.insert_new_session(session(publisher));
+        switchboard
+            .create_stream(stream_id, SessionId::new(publisher), agent_id, None)
+            .unwrap();
+
+        // The default one is taken from the app config.
+        let writer_config = WriterConfig::with_video_remb(1_000_000);
+        switchboard.set_writer_config(stream_id, writer_config);
+    }
+
+    /// Joins a new session of the agent to the stream.
+    fn join(
+        switchboard: &mut Switchboard,
+        stream_id: StreamId,
+        reader: u64,
+        agent_id: &str,
+    ) -> Result<(), SwitchboardError> {
+        switchboard.insert_new_session(session(reader));
+        switchboard.join_stream(stream_id, SessionId::new(reader), agent_id.to_owned())
+    }
+
+    fn pause(switchboard: &mut Switchboard, stream_id: StreamId) {
+        switchboard.paused_streams.insert(
+            stream_id,
+            PausedStream {
+                since: Instant::now(),
+                readers: vec![],
+            },
+        );
+    }
+
+    #[test]
+    fn typed_errors() {
+        let mut switchboard = Switchboard::new(config());
+        let stream_id = Uuid::new_v4();
+        let agent_id = String::from("web.john.usr.example.org");
+
+        match switchboard.join_stream(stream_id, SessionId::new(1), agent_id.clone()) {
+            Err(err @ SwitchboardError::StreamNotFound(_)) => {
+                assert_eq!(err.status(), StatusCode::NOT_FOUND);
+                assert_eq!(err.kind().0, "stream_not_found");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        match switchboard.create_stream(stream_id, SessionId::new(1), agent_id, None) {
+            Err(SwitchboardError::SessionNotFound(id)) => assert_eq!(id, SessionId::new(1)),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn paused_stream_expires_after_grace_period() {
+        let mut switchboard = Switchboard::new(SwitchboardConfig {
+            publisher_grace_period: Some(Duration::from_millis(50)),
+            ..config()
+        });
+
+        let stream_id = Uuid::new_v4();
+        let agent_id = "web.john.usr.example.org";
+        pause(&mut switchboard, stream_id);
+
+        // The stream is still joinable while waiting for its publisher.
+        join(&mut switchboard, stream_id, 1, agent_id).unwrap();
+        assert_eq!(
+            switchboard.stream_readers(stream_id),
+            vec![SessionId::new(1)]
+        );
+
+        switchboard.vacuum_paused_streams();
+        assert!(switchboard.paused_streams.contains_key(&stream_id));
+
+        thread::sleep(Duration::from_millis(50));
+        switchboard.vacuum_paused_streams();
+        assert!(!switchboard.paused_streams.contains_key(&stream_id));
+
+        match join(&mut switchboard, stream_id, 2, agent_id) {
+            Err(SwitchboardError::StreamNotFound(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn takeover_policy() {
+        let mut switchboard = Switchboard::new(SwitchboardConfig {
+            publisher_grace_period: Some(Duration::from_secs(60)),
+            takeover_policy: TakeoverPolicy::SameAgentOnly,
+            ..config()
+        });
+
+        let stream_id = Uuid::new_v4();
+        let owner = String::from("web.john.usr.example.org");
+        let stranger = String::from("web.jane.usr.example.org");
+
+        let create = |switchboard: &mut Switchboard, id, agent_id: &AgentId, policy| {
+            switchboard.insert_new_session(session(id));
+            switchboard.create_stream(stream_id, SessionId::new(id), agent_id.clone(), policy)
+        };
+
+        create(&mut switchboard, 1, &owner, Some(TakeoverPolicy::Deny)).unwrap();
+
+        // The challenger can't weaken the owner's policy.
+        match create(&mut switchboard, 2, &stranger, Some(TakeoverPolicy::Allow)) {
+            Err(err @ SwitchboardError::TakeoverDenied { .. }) => {
+                assert_eq!(err.status(), StatusCode::FORBIDDEN);
+                assert_eq!(err.kind().0, "stream_takeover_denied");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // The owner may only resume a paused stream and the policy stays.
+        assert!(create(&mut switchboard, 3, &owner, None).is_err());
+        switchboard.handle_disconnect(SessionId::new(1)).unwrap();
+        create(&mut switchboard, 4, &owner, None).unwrap();
+        assert!(create(&mut switchboard, 5, &stranger, Some(TakeoverPolicy::Allow)).is_err());
 
-    use crate::{conf::SpeakingNotifications, janus_rtp::AudioLevel};
+        // The owner changes the policy on resuming the stream.
+        switchboard.handle_disconnect(SessionId::new(4)).unwrap();
+        create(&mut switchboard, 6, &owner, Some(TakeoverPolicy::Allow)).unwrap();
+        create(&mut switchboard, 7, &stranger, None).unwrap();
 
-    use super::SessionState;
+        // A new owner starts over with the configured policy.
+        assert!(create(&mut switchboard, 8, &owner, None).is_err());
+    }
+
+    #[test]
+    fn reader_limits() {
+        let mut switchboard = Switchboard::new(config());
+        let stream_id = Uuid::new_v4();
+        let moderator = "web.moderator.usr.example.org";
+        let reader = "web.john.usr.example.org";
+        let another_reader = "web.jane.usr.example.org";
+
+        publish(&mut switchboard, stream_id, 1);
+        join(&mut switchboard, stream_id, 2, reader).unwrap();
+        let limits = ReaderLimits::new(2, vec![moderator.to_owned()]);
+        switchboard.set_reader_limits(stream_id, limits);
+
+        // The last seat is kept for the moderator.
+        let result = join(&mut switchboard, stream_id, 3, another_reader);
+        assert!(matches!(result, Err(SwitchboardError::SeatsReserved(_))));
+
+        join(&mut switchboard, stream_id, 4, moderator).unwrap();
+
+        match join(&mut switchboard, stream_id, 5, another_reader) {
+            Err(err @ SwitchboardError::StreamFull(_)) => {
+                assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(err.kind().0, "stream_full");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // A reconnecting agent keeps its seat while the previous session gets evicted.
+        join(&mut switchboard, stream_id, 6, reader).unwrap();
+    }
+
+    #[test]
+    fn reject_newest_session() {
+        let mut switchboard = Switchboard::new(SwitchboardConfig {
+            sessions_policy: SessionsPolicy::RejectNewest,
+            ..config()
+        });
+
+        let stream_id = Uuid::new_v4();
+        let agent_id = "web.john.usr.example.org";
+        publish(&mut switchboard, stream_id, 1);
+        join(&mut switchboard, stream_id, 2, agent_id).unwrap();
+
+        match join(&mut switchboard, stream_id, 3, agent_id) {
+            Err(err @ SwitchboardError::TooManySessions(_)) => {
+                assert_eq!(err.status(), StatusCode::CONFLICT);
+                assert_eq!(err.kind().0, "too_many_sessions");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn vacuum_readers() {
+        let mut switchboard = Switchboard::new(config());
+        let stream_id = Uuid::new_v4();
+        publish(&mut switchboard, stream_id, 1);
+        join(&mut switchboard, stream_id, 2, "web.reader.usr.example.org").unwrap();
+
+        let publisher = switchboard.session(SessionId::new(1)).unwrap();
+        let reader = switchboard.session(SessionId::new(2)).unwrap();
+
+        // The reader hasn't sent RTCP yet so the timeout counts from joining.
+        switchboard
+            .vacuum_readers(&chrono::Duration::seconds(60))
+            .unwrap();
+
+        assert!(!is_ended(reader));
+
+        switchboard
+            .vacuum_readers(&chrono::Duration::zero())
+            .unwrap();
+        assert!(is_ended(reader));
+        assert!(!is_ended(publisher));
+    }
+
+    /// Starts a takeover of the stream read by session 2 by publisher 3 from publisher 1.
+    fn start_takeover(switchboard: &mut Switchboard, stream_id: StreamId) {
+        publish(switchboard, stream_id, 1);
+        join(switchboard, stream_id, 2, "web.reader.usr.example.org").unwrap();
+        publish(switchboard, stream_id, 3);
+        assert!(switchboard.takeovers.contains_key(&stream_id));
+        assert_eq!(
+            switchboard.subscribers_to(SessionId::new(1)),
+            [SessionId::new(2)]
+        );
+    }
+
+    #[test]
+    fn finish_takeover_when_old_publisher_leaves() {
+        let mut switchboard = Switchboard::new(config());
+        let stream_id = Uuid::new_v4();
+        start_takeover(&mut switchboard, stream_id);
+
+        switchboard.handle_disconnect(SessionId::new(1)).unwrap();
+        assert!(!switchboard.takeovers.contains_key(&stream_id));
+        assert_eq!(switchboard.publisher_of(stream_id), Some(SessionId::new(3)));
+        assert_eq!(
+            switchboard.subscribers_to(SessionId::new(3)),
+            [SessionId::new(2)]
+        );
+    }
+
+    #[test]
+    fn vacuum_takeovers() {
+        let mut switchboard = Switchboard::new(SwitchboardConfig {
+            takeover_keyframe_timeout: Duration::from_millis(50),
+            ..config()
+        });
+
+        let stream_id = Uuid::new_v4();
+        start_takeover(&mut switchboard, stream_id);
+
+        switchboard.vacuum_takeovers();
+        assert!(switchboard.takeovers.contains_key(&stream_id));
+
+        thread::sleep(Duration::from_millis(50));
+        switchboard.vacuum_takeovers();
+        assert!(!switchboard.takeovers.contains_key(&stream_id));
+        assert!(switchboard.subscribers_to(SessionId::new(1)).is_empty());
+        assert_eq!(
+            switchboard.subscribers_to(SessionId::new(3)),
+            [SessionId::new(2)]
+        );
+    }
+
+    #[test]
+    fn cancel_takeover_when_new_publisher_leaves() {
+        let mut switchboard = Switchboard::new(config());
+        let stream_id = Uuid::new_v4();
+        start_takeover(&mut switchboard, stream_id);
+        join(
+            &mut switchboard,
+            stream_id,
+            4,
+            "web.latecomer.usr.example.org",
+        )
+        .unwrap();
+
+        switchboard.handle_disconnect(SessionId::new(3)).unwrap();
+        assert!(!switchboard.takeovers.contains_key(&stream_id));
+        assert_eq!(switchboard.publisher_of(stream_id), Some(SessionId::new(1)));
+
+        let readers = [SessionId::new(2), SessionId::new(4)];
+        assert_eq!(switchboard.subscribers_to(SessionId::new(1)), readers);
+
+        let owner = String::from("web.publisher1.usr.example.org");
+        assert_eq!(switchboard.owner_of(stream_id), Some(&owner));
+        assert_eq!(switchboard.agent_id(SessionId::new(1)), Some(&owner));
+        assert!(switchboard.writer_configs.contains_key(&stream_id));
+    }
+
+    #[test]
+    fn relay_table_rebuilt_on_changes_only() {
+        let mut switchboard = Switchboard::new(config());
+        assert!(switchboard.take_relay_changes().is_empty());
+        assert!(switchboard.remove_stream(Uuid::new_v4()).unwrap().is_none());
+        assert!(switchboard.take_relay_changes().is_empty());
+
+        let streams = [Uuid::new_v4(), Uuid::new_v4()];
+        publish(&mut switchboard, streams[0], 1);
+        publish(&mut switchboard, streams[1], 2);
+        join(
+            &mut switchboard,
+            streams[0],
+            3,
+            "web.reader1.usr.example.org",
+        )
+        .unwrap();
+
+        let changes = switchboard.take_relay_changes();
+        let relay_table = switchboard.relay_table(&RelayTable::default(), &changes);
+        let reader = relay_table.session(SessionId::new(3)).unwrap();
+        assert_eq!(reader.publisher(), Some(SessionId::new(1)));
+
+        // Only the publisher the reader joins to gets its forwarding table rebuilt.
+        let reader_id = String::from("web.reader2.usr.example.org");
+        join(&mut switchboard, streams[0], 4, &reader_id).unwrap();
+
+        let changes = switchboard.take_relay_changes();
+        let expected_changes = [SessionId::new(1), SessionId::new(4)];
+        assert_eq!(changes, expected_changes.iter().copied().collect());
+
+        let next_relay_table = switchboard.relay_table(&relay_table, &changes);
+        let forwarding = |relay_table: &RelayTable, id| {
+            relay_table
+                .session(SessionId::new(id))
+                .unwrap()
+                .forwarding()
+                .unwrap()
+                .clone()
+        };
+
+        assert_eq!(forwarding(&next_relay_table, 1).readers().len(), 2);
+
+        assert!(Arc::ptr_eq(
+            &forwarding(&relay_table, 2),
+            &forwarding(&next_relay_table, 2)
+        ));
+
+        switchboard.update_reader_config(streams[1], &reader_id, ReaderConfig::new(true, false));
+        let changes = switchboard.take_relay_changes();
+        assert_eq!(changes, Some(SessionId::new(2)).into_iter().collect());
+    }
+
+    #[test]
+    fn forwarding_table_split_by_workers() {
+        let mut switchboard = Switchboard::new(SwitchboardConfig {
+            fan_out_workers: 3,
+            ..config()
+        });
+
+        let stream_id = Uuid::new_v4();
+        publish(&mut switchboard, stream_id, 1);
+
+        for reader in 10..20 {
+            let agent_id = format!("web.reader{}.usr.example.org", reader);
+            join(&mut switchboard, stream_id, reader, &agent_id).unwrap();
+        }
+
+        let changes = switchboard.take_relay_changes();
+        let relay_table = switchboard.relay_table(&RelayTable::default(), &changes);
+        let publisher = relay_table.session(SessionId::new(1)).unwrap();
+        let forwarding = publisher.forwarding().unwrap();
+        let mut readers_count = 0;
+
+        for shard in 0..3 {
+            let readers = forwarding.shard_readers(shard);
+            assert!(readers.iter().all(|reader| reader.id().shard(3) == shard));
+            readers_count += readers.len();
+        }
+
+        assert_eq!(readers_count, 10);
+        assert!(forwarding.shard_readers(3).is_empty());
+    }
 
     #[test]
     fn test_speaking_notification() {
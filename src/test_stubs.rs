@@ -1,10 +1,19 @@
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_uint};
 /// This modules defines stubs for functions from janus-plugin-sys crate to enable linking when
 /// compiling for running unit tests.
 use std::ptr;
+use std::sync::{Mutex, Once};
 
-use janus_plugin_sys::sdp::janus_sdp;
+use janus::{
+    session::SessionWrapper, Plugin, PluginCallbacks, PluginDataPacket, PluginRtcpPacket,
+    PluginRtpPacket, PluginSession, RawJanssonValue,
+};
+use janus_plugin_sys::{janus_refcount, plugin::janus_plugin_result_type, sdp::janus_sdp};
 use libc::c_void;
+use once_cell::sync::Lazy;
+
+use crate::janus_callbacks;
+use crate::switchboard::{Session, SessionId};
 
 // lib.rs
 
@@ -54,3 +63,176 @@ pub extern "C" fn janus_sdp_write(_sdp: *mut janus_sdp) -> *mut c_char {
 
 #[no_mangle]
 pub extern "C" fn janus_sdp_destroy(_sdp: *mut janus_sdp) {}
+
+// plugin.rs
+
+#[no_mangle]
+pub extern "C" fn janus_plugin_result_new(
+    _type: janus_plugin_result_type,
+    _text: *const c_char,
+    _content: *mut c_void,
+) -> *mut c_void {
+    ptr::null_mut()
+}
+
+// rtcp.rs
+
+#[no_mangle]
+pub extern "C" fn janus_rtcp_has_fir(_packet: *mut c_char, _len: c_int) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn janus_rtcp_has_pli(_packet: *mut c_char, _len: c_int) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn janus_rtcp_remb(_packet: *mut c_char, _len: c_int, _bitrate: u32) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn janus_rtcp_fir(_packet: *mut c_char, _len: c_int, _seqnr: *mut c_int) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn janus_rtcp_pli(_packet: *mut c_char, _len: c_int) -> c_int {
+    0
+}
+
+// janus_recorder.rs
+
+#[no_mangle]
+pub extern "C" fn janus_recorder_create(
+    _dir: *const c_char,
+    _codec: *const c_char,
+    _filename: *const c_char,
+) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn janus_recorder_save_frame(
+    _recorder: *mut c_void,
+    _buffer: *const c_char,
+    _length: c_uint,
+) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn janus_recorder_close(_recorder: *mut c_void) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn janus_recorder_destroy(_recorder: *mut c_void) {}
+
+// Janus core
+
+extern "C" fn push_event(
+    _handle: *mut PluginSession,
+    _plugin: *mut Plugin,
+    _transaction: *const c_char,
+    _message: *mut RawJanssonValue,
+    _jsep: *mut RawJanssonValue,
+) -> c_int {
+    0
+}
+
+extern "C" fn relay_rtp(_handle: *mut PluginSession, _packet: *mut PluginRtpPacket) {}
+
+extern "C" fn relay_rtcp(_handle: *mut PluginSession, _packet: *mut PluginRtcpPacket) {}
+
+extern "C" fn relay_data(_handle: *mut PluginSession, _packet: *mut PluginDataPacket) {}
+
+extern "C" fn handle_callback(_handle: *mut PluginSession) {}
+
+/// Handles Janus has been asked to end.
+static ENDED_HANDLES: Lazy<Mutex<Vec<usize>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+extern "C" fn end_session(handle: *mut PluginSession) {
+    ENDED_HANDLES
+        .lock()
+        .expect("Ended handles lock poisoned")
+        .push(handle as usize);
+}
+
+/// Whether Janus has been asked to end the session.
+pub fn is_ended(session: &Session) -> bool {
+    ENDED_HANDLES
+        .lock()
+        .expect("Ended handles lock poisoned")
+        .contains(&(session.as_ptr() as usize))
+}
+
+extern "C" fn send_remb(_handle: *mut PluginSession, _bitrate: c_int) {}
+
+extern "C" fn events_is_enabled() -> c_int {
+    0
+}
+
+extern "C" fn notify_event(
+    _plugin: *mut Plugin,
+    _handle: *mut PluginSession,
+    _event: *mut RawJanssonValue,
+) {
+}
+
+extern "C" fn auth_is_signature_valid(_plugin: *mut Plugin, _token: *const c_char) -> c_int {
+    0
+}
+
+extern "C" fn auth_signature_contains(
+    _plugin: *mut Plugin,
+    _token: *const c_char,
+    _descriptor: *const c_char,
+) -> c_int {
+    0
+}
+
+static CALLBACKS: PluginCallbacks = PluginCallbacks {
+    push_event,
+    relay_rtp,
+    relay_rtcp,
+    relay_data,
+    send_pli: handle_callback,
+    send_remb,
+    close_pc: handle_callback,
+    end_session,
+    events_is_enabled,
+    notify_event,
+    auth_is_signature_valid,
+    auth_signature_contains,
+};
+
+static INIT_CALLBACKS: Once = Once::new();
+
+/// Makes Janus callbacks do nothing.
+pub fn init_callbacks() {
+    INIT_CALLBACKS.call_once(|| {
+        janus_callbacks::init(&CALLBACKS as *const PluginCallbacks as *mut PluginCallbacks)
+    });
+}
+
+extern "C" fn free_handle(_refcount: *const janus_refcount) {}
+
+/// Creates a session as if Janus has attached a handle.
+pub fn session(id: u64) -> Session {
+    init_callbacks();
+
+    let handle = Box::leak(Box::new(PluginSession {
+        gateway_handle: ptr::null_mut(),
+        plugin_handle: ptr::null_mut(),
+        stopped: 0,
+        ref_: janus_refcount {
+            count: 1,
+            free: free_handle,
+        },
+    }));
+
+    unsafe { SessionWrapper::associate(handle, SessionId::new(id)) }
+        .expect("Failed to associate session")
+}